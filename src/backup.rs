@@ -0,0 +1,238 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{
+    dusb::{VariableKind, VariableName},
+    tifiles::{TiFile, TiFileEntry},
+};
+
+/// Whether a backup is gzip compressed, which it is if its path ends in `.gz`. DUSB has no
+/// compressed transfer mode, so this only saves space on disk.
+fn is_compressed(path: &Path) -> bool {
+    path.extension() == Some("gz".as_ref())
+}
+
+/// `path` with `suffix` added after its extension.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+/// Writes a group file, compressing it if the path ends in `.gz`. It's written to a temporary
+/// file first and then renamed, so an interruption never leaves half a group at `path`.
+pub fn write_group(path: &Path, group: &TiFile) -> anyhow::Result<()> {
+    let temporary = with_suffix(path, ".tmp");
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    if is_compressed(path) {
+        let mut encoder = GzEncoder::new(&mut writer, Compression::default());
+        group.write(&mut encoder)?;
+        encoder.finish()?;
+    } else {
+        group.write(&mut writer)?;
+    }
+    writer.into_inner().map_err(io::Error::from)?.sync_all()?;
+
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+/// Reads a group file written by [`write_group`], decompressing it if the path ends in `.gz`.
+pub fn read_group(path: &Path) -> anyhow::Result<TiFile> {
+    let mut bytes = fs::read(path)?;
+    if is_compressed(path) {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed)?;
+        bytes = decompressed;
+    }
+
+    TiFile::read(&bytes)
+}
+
+/// The variables saved so far by a backup that hasn't finished, kept next to the group file.
+/// Each one is appended as it's pulled, laid out like the entries in a TI file, so saving a
+/// variable doesn't mean rewriting everything before it.
+///
+/// Alongside it is a manifest listing what's been saved so far, one variable per line as its
+/// kind, size and name:
+///
+/// ```text
+/// String 6 bytes Str1
+/// ```
+///
+/// The manifest is only for reading along. It's rewritten from the partial backup whenever
+/// that's opened, so the two always agree.
+#[derive(Debug)]
+pub struct PartialBackup {
+    path: PathBuf,
+    file: File,
+    manifest: File,
+    entries: Vec<TiFileEntry>,
+}
+
+impl PartialBackup {
+    /// Where the partial backup for the group file at `path` is kept.
+    pub fn path_for(path: &Path) -> PathBuf {
+        with_suffix(path, ".partial")
+    }
+
+    /// Where the manifest for the partial backup at `path` is kept.
+    pub fn manifest_path(path: &Path) -> PathBuf {
+        with_suffix(path, ".txt")
+    }
+
+    /// Opens the partial backup at `path`, or starts a new one if there isn't one. An entry that
+    /// was cut off by an interruption is dropped, so that variable is pulled again.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut entries = Vec::new();
+        let mut remaining = bytes.as_slice();
+        let mut complete = 0;
+        while let Ok(entry) = TiFileEntry::read(&mut remaining) {
+            entries.push(entry);
+            complete = bytes.len() - remaining.len();
+        }
+        if complete < bytes.len() {
+            println!(
+                "[Dropping {} bytes left over from an interrupted backup]",
+                bytes.len() - complete
+            );
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.set_len(complete as u64)?;
+
+        let mut manifest = File::create(Self::manifest_path(path))?;
+        for entry in &entries {
+            write_manifest_line(&mut manifest, entry)?;
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            manifest,
+            entries,
+        })
+    }
+
+    pub fn contains(&self, name: &VariableName, kind: VariableKind) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.name == *name && entry.type_id == kind.file_type_byte())
+    }
+
+    /// Adds a variable and writes it to disk straight away.
+    pub fn record(&mut self, entry: TiFileEntry) -> anyhow::Result<()> {
+        let mut bytes = Vec::new();
+        entry.write(&mut bytes)?;
+        self.file.write_all(&bytes)?;
+        self.file.sync_data()?;
+        write_manifest_line(&mut self.manifest, &entry)?;
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes everything saved to a group file at `path` and removes the partial backup. If the
+    /// group would be too large for a TI file, this fails and the partial backup is kept.
+    pub fn finish(self, path: &Path) -> anyhow::Result<TiFile> {
+        let group = TiFile::new(self.entries);
+        write_group(path, &group)?;
+        fs::remove_file(&self.path)?;
+        fs::remove_file(Self::manifest_path(&self.path))?;
+
+        Ok(group)
+    }
+}
+
+fn write_manifest_line(manifest: &mut File, entry: &TiFileEntry) -> io::Result<()> {
+    let kind = match VariableKind::from_file_type_byte(entry.type_id) {
+        Some(kind) => format!("{kind:?}"),
+        None => format!("Type{:02x}", entry.type_id),
+    };
+    writeln!(manifest, "{kind} {} bytes {}", entry.data.len(), entry.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, kind: VariableKind, data: &[u8]) -> TiFileEntry {
+        TiFileEntry {
+            name: VariableName::from(name),
+            type_id: kind.file_type_byte(),
+            version: 0,
+            archived: false,
+            data: data.to_vec(),
+        }
+    }
+
+    #[test]
+    fn reopening_keeps_saved_variables() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = PartialBackup::path_for(&directory.path().join("backup.8xg"));
+
+        let mut partial = PartialBackup::open(&path).unwrap();
+        partial
+            .record(entry("Str1", VariableKind::String, &[1, 0, b'A']))
+            .unwrap();
+        partial
+            .record(entry("PROG", VariableKind::Program, &[1, 0, 0xde]))
+            .unwrap();
+        drop(partial);
+        // Half of a third entry, cut off by the interruption
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[0x0d, 0, 3, 0, 0x04]).unwrap();
+        drop(file);
+
+        let partial = PartialBackup::open(&path).unwrap();
+        assert!(partial.contains(&VariableName::from("Str1"), VariableKind::String));
+        assert!(partial.contains(&VariableName::from("PROG"), VariableKind::Program));
+        assert!(!partial.contains(&VariableName::from("PROG"), VariableKind::String));
+        assert_eq!(
+            fs::read_to_string(PartialBackup::manifest_path(&path)).unwrap(),
+            "String 3 bytes Str1\nProgram 3 bytes PROG\n"
+        );
+
+        let group_path = directory.path().join("backup.8xg");
+        let group = partial.finish(&group_path).unwrap();
+        assert_eq!(group.entries.len(), 2);
+        assert!(!path.exists());
+        assert!(!PartialBackup::manifest_path(&path).exists());
+    }
+
+    #[test]
+    fn compressed_group_round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let group = TiFile::new(vec![
+            entry("Str1", VariableKind::String, &[1, 0, b'A']),
+            entry("PROG", VariableKind::Program, &[1, 0, 0xde]),
+        ]);
+
+        for name in ["backup.8xg", "backup.8xg.gz"] {
+            let path = directory.path().join(name);
+            write_group(&path, &group).unwrap();
+            let read = read_group(&path).unwrap();
+
+            assert_eq!(read.entries.len(), 2);
+            for (read, written) in read.entries.iter().zip(&group.entries) {
+                assert_eq!(read.name, written.name);
+                assert_eq!(read.type_id, written.type_id);
+                assert_eq!(read.data, written.data);
+            }
+        }
+        let compressed = fs::read(directory.path().join("backup.8xg.gz")).unwrap();
+        assert_eq!(compressed[..2], [0x1f, 0x8b]);
+    }
+}
@@ -2,10 +2,14 @@ use core::fmt;
 use std::io;
 
 use byteorder::{ByteOrder, ReadBytesExt, BE, LE};
-use strum::{EnumDiscriminants, FromRepr};
+use strum::{EnumDiscriminants, EnumIter, FromRepr, IntoEnumIterator};
 use thiserror::Error;
 
-use crate::util::{u16_from_bytes, u32_from_bytes};
+use crate::{
+    keys::KeyCode,
+    model::Model,
+    util::{u16_from_bytes, u32_from_bytes},
+};
 
 #[repr(u8)]
 #[derive(Debug, FromRepr)]
@@ -28,9 +32,31 @@ impl From<Mode> for Vec<u8> {
     }
 }
 
-impl From<&[u8]> for Mode {
-    fn from(value: &[u8]) -> Self {
-        Self::from_repr(value[1]).unwrap()
+#[derive(Error, Debug)]
+pub struct UnknownModeError(pub u16);
+impl fmt::Display for UnknownModeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown mode {}", self.0)
+    }
+}
+
+/// Something for the calculator to run remotely. Programs and apps are started with the same
+/// packet, but only key presses are supported so far.
+#[derive(Debug, Clone)]
+pub enum ExecuteAction {
+    Key(KeyCode),
+}
+
+impl From<ExecuteAction> for Vec<u8> {
+    fn from(value: ExecuteAction) -> Self {
+        match value {
+            ExecuteAction::Key(key) => {
+                // Empty name, then the action ID and the key code
+                let mut payload = vec![0, 0, 3];
+                payload.extend_from_slice(&key.0.to_le_bytes());
+                payload
+            }
+        }
     }
 }
 
@@ -45,93 +71,697 @@ pub enum VariableAttribute {
     AppVarSource(u32) = 0x05,
     Version(u8) = 0x08,
     Kind2(u32) = 0x11,
+    Archived2(bool) = 0x13,
     Locked(bool) = 0x41,
+    /// Any attribute ID not listed above, stored with its real ID and undecoded data
+    Unknown(u16, Vec<u8>) = 0xffff,
 }
 
 impl VariableAttribute {
-    pub fn from_payload(kind: VariableAttributeKind, mut payload: &[u8]) -> anyhow::Result<Self> {
+    /// Decodes an attribute from its on-wire ID, falling back to [`Self::Unknown`] for IDs
+    /// that aren't known yet so the rest of the header can still be read. Known attributes
+    /// with the wrong amount of data fail with [`WrongAttributeLength`].
+    pub fn from_id(id: u16, mut payload: &[u8]) -> anyhow::Result<Self> {
+        let kind = match VariableAttributeKind::from_repr(id) {
+            Some(VariableAttributeKind::Unknown) | None => {
+                return Ok(Self::Unknown(id, payload.to_vec()))
+            }
+            Some(kind) => kind,
+        };
+        if let Some(expected) = kind.data_length() {
+            if payload.len() != expected {
+                return Err(WrongAttributeLength {
+                    kind,
+                    expected,
+                    received: payload.len(),
+                }
+                .into());
+            }
+        }
+
         Ok(match kind {
             VariableAttributeKind::Size => Self::Size(payload.read_u32::<BE>()?),
             VariableAttributeKind::Kind => Self::Kind(payload.read_u32::<BE>()?),
-            VariableAttributeKind::Archived => Self::Archived(payload.read_u8()? == 1), // Guessing that 1 == true, need to verify
+            VariableAttributeKind::Archived => Self::Archived(payload.read_u8()? == 1),
             VariableAttributeKind::AppVarSource => Self::AppVarSource(payload.read_u32::<BE>()?),
             VariableAttributeKind::Version => Self::Version(payload.read_u8()?),
             VariableAttributeKind::Kind2 => Self::Kind2(payload.read_u32::<BE>()?),
-            VariableAttributeKind::Locked => Self::Locked(payload.read_u8()? == 1), // Also guessing here
+            VariableAttributeKind::Archived2 => Self::Archived2(payload.read_u8()? == 1),
+            VariableAttributeKind::Locked => Self::Locked(payload.read_u8()? == 1),
+            VariableAttributeKind::Unknown => Self::Unknown(id, payload.to_vec()),
         })
     }
 
+    pub fn id(&self) -> u16 {
+        match self {
+            Self::Unknown(id, _) => *id,
+            attribute => VariableAttributeKind::from(attribute) as u16,
+        }
+    }
+
     pub fn into_payload(self) -> Vec<u8> {
         match self {
             Self::Size(size) => size.to_be_bytes().to_vec(),
             Self::Kind(kind) => kind.to_be_bytes().to_vec(),
-            Self::Archived(archived) => {
-                if archived {
-                    vec![0; 1]
-                } else {
-                    vec![1; 1]
-                }
-            }
+            Self::Archived(archived) => vec![archived as u8],
             Self::AppVarSource(source) => source.to_be_bytes().to_vec(),
             Self::Version(version) => version.to_be_bytes().to_vec(),
             Self::Kind2(kind) => kind.to_be_bytes().to_vec(),
-            Self::Locked(locked) => {
-                if locked {
-                    vec![0; 1]
-                } else {
-                    vec![1; 1]
-                }
-            }
+            Self::Archived2(archived) => vec![archived as u8],
+            Self::Locked(locked) => vec![locked as u8],
+            Self::Unknown(_, data) => data,
+        }
+    }
+}
+
+impl VariableAttributeKind {
+    /// Every attribute that can be requested. Not every calculator has all of them, the ones it
+    /// doesn't are marked as unavailable for each variable and left out of its attributes.
+    pub const ALL: &'static [Self] = &[
+        Self::Size,
+        Self::Kind,
+        Self::Archived,
+        Self::AppVarSource,
+        Self::Version,
+        Self::Kind2,
+        Self::Archived2,
+        Self::Locked,
+    ];
+
+    /// How many bytes of data this attribute has, or `None` if it can be any length.
+    pub fn data_length(self) -> Option<usize> {
+        match self {
+            Self::Size | Self::Kind | Self::AppVarSource | Self::Kind2 => Some(4),
+            Self::Archived | Self::Version | Self::Archived2 | Self::Locked => Some(1),
+            Self::Unknown => None,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("wrong length for {kind:?} attribute: expected {expected} bytes, received {received}")]
+pub struct WrongAttributeLength {
+    pub kind: VariableAttributeKind,
+    pub expected: usize,
+    pub received: usize,
+}
+
+/// A variable name exactly as the calculator stores it. Names are in the calculator's own
+/// character set rather than UTF-8 (θ is 0x5b, for example), so they're kept as bytes and only
+/// converted for display.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VariableName(pub Vec<u8>);
+
+impl VariableName {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<&str> for VariableName {
+    fn from(name: &str) -> Self {
+        Self(name.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for VariableName {
+    fn from(name: String) -> Self {
+        Self(name.into_bytes())
+    }
+}
+
+impl From<&VariableName> for VariableName {
+    fn from(name: &VariableName) -> Self {
+        name.clone()
+    }
+}
+
+impl From<Vec<u8>> for VariableName {
+    fn from(name: Vec<u8>) -> Self {
+        Self(name)
+    }
+}
+
+impl fmt::Display for VariableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use fmt::Write;
+
+        for &byte in &self.0 {
+            f.write_char(match byte {
+                0x5b => 'θ',
+                0x80..=0x89 => char::from_u32('₀' as u32 + (byte - 0x80) as u32).unwrap(),
+                0xeb => '∟',
+                0x20..=0x7e => byte as char,
+                _ => char::REPLACEMENT_CHARACTER,
+            })?;
         }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for VariableName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct Variable {
-    pub name: String,
+    pub name: VariableName,
     pub attributes: Vec<VariableAttribute>,
+    /// The requested attributes the calculator marked as invalid instead of sending.
+    pub unavailable: Vec<Unavailable>,
+}
+
+impl Variable {
+    pub fn size(&self) -> Option<u32> {
+        self.attributes.iter().find_map(|attr| {
+            if let VariableAttribute::Size(size) = attr {
+                Some(*size)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn kind(&self) -> Option<u32> {
+        self.attributes.iter().find_map(|attr| {
+            if let VariableAttribute::Kind(kind) = attr {
+                Some(*kind)
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn version(&self) -> Option<u8> {
+        self.attributes.iter().find_map(|attr| {
+            if let VariableAttribute::Version(version) = attr {
+                Some(*version)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Where the variable is stored, or `None` if neither `Archived` attribute was requested.
+    pub fn location(&self) -> Option<StorageLocation> {
+        self.attributes.iter().find_map(|attr| {
+            if let VariableAttribute::Archived(archived) | VariableAttribute::Archived2(archived) =
+                attr
+            {
+                Some(if *archived {
+                    StorageLocation::Archive
+                } else {
+                    StorageLocation::Ram
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether the variable is locked, or `None` if the `Locked` attribute wasn't requested.
+    pub fn locked(&self) -> Option<bool> {
+        self.attributes.iter().find_map(|attr| {
+            if let VariableAttribute::Locked(locked) = attr {
+                Some(*locked)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Checks that `stored`, this variable's header as read back after sending it, has the
+    /// same `Archived` and `Locked` attributes. Attributes this header doesn't have aren't
+    /// checked.
+    pub fn check_applied(&self, stored: &Variable) -> Result<(), AttributeNotApplied> {
+        let archived = |variable: &Variable| {
+            variable
+                .location()
+                .map(|location| location == StorageLocation::Archive)
+        };
+
+        for (attribute, sent, stored) in [
+            (
+                VariableAttributeKind::Archived,
+                archived(self),
+                archived(stored),
+            ),
+            (
+                VariableAttributeKind::Locked,
+                self.locked(),
+                stored.locked(),
+            ),
+        ] {
+            if let Some(sent) = sent {
+                if stored != Some(sent) {
+                    return Err(AttributeNotApplied {
+                        name: self.name.clone(),
+                        attribute,
+                        sent,
+                        stored,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// One line with the name, then whichever of the kind, size and flags were requested, like
+/// `Str1 (String, 4 bytes, archived)`.
+impl fmt::Display for Variable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut details = Vec::new();
+        for attribute in &self.attributes {
+            match attribute {
+                VariableAttribute::Kind(kind) => details.push(match VariableKind::from_id(*kind) {
+                    Some(kind) => format!("{kind:?}"),
+                    None => format!("kind {kind:#010x}"),
+                }),
+                VariableAttribute::Size(size) => details.push(format!("{size} bytes")),
+                VariableAttribute::Archived(true) => details.push("archived".to_string()),
+                VariableAttribute::Locked(true) => details.push("locked".to_string()),
+                _ => {}
+            }
+        }
+
+        write!(f, "{}", self.name)?;
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// An installed Flash app, see [`Calculator::list_apps`](crate::Calculator::list_apps).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppInfo {
+    pub name: VariableName,
+    pub size: u32,
+    /// How many Flash pages the app takes up, or `None` if the model's page size isn't known.
+    pub pages: Option<u32>,
+}
+
+#[derive(Debug, Error)]
+#[error("not enough free archive space for the variable")]
+pub struct ArchiveFull;
+
+#[derive(Debug, Error)]
+#[error(
+    "{name} read back differently than it was sent ({sent} bytes sent, {received} bytes read back)"
+)]
+pub struct VerificationFailed {
+    pub name: VariableName,
+    pub sent: usize,
+    pub received: usize,
+}
+
+#[derive(Debug, Error)]
+#[error("{name} was sent with {attribute:?}({sent}), but read back with {stored:?}")]
+pub struct AttributeNotApplied {
+    pub name: VariableName,
+    pub attribute: VariableAttributeKind,
+    pub sent: bool,
+    pub stored: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StorageLocation {
+    Ram,
+    Archive,
+}
+
+/// Sorts variables by name, then kind, then storage location so that listings from two
+/// different calculators (or the same one at different times) can be compared directly.
+pub fn sort_variables(variables: &mut [Variable]) {
+    variables
+        .sort_by(|a, b| (&a.name, a.kind(), a.location()).cmp(&(&b.name, b.kind(), b.location())));
 }
 
 #[repr(u32)]
-#[derive(Debug, EnumDiscriminants)]
+#[derive(Debug, Clone, EnumDiscriminants)]
 #[strum_discriminants(name(VariableKind))]
-#[strum_discriminants(derive(FromRepr))]
+#[strum_discriminants(derive(FromRepr, EnumIter))]
 pub enum VariableContents {
     Image(Vec<u8>) = 0xf00e001a,
+    /// A graph equation like Y1, as tokens.
+    Equation(Vec<u8>) = 0xf0070003,
     String(String) = 0xf0070004,
+    Program(Vec<u8>) = 0xf0070005,
+    ProtectedProgram(Vec<u8>) = 0xf0070006,
+    Picture(Vec<u8>) = 0xf0070007,
+    AppVar(Vec<u8>) = 0xf0070015,
     App(Vec<u8>) = 0xf00f0024,
+    /// Any kind not listed above, stored with its real kind and undecoded data
+    Unknown(u32, Vec<u8>) = 0xffffffff,
 }
 
 impl VariableContents {
-    pub fn from_payload(kind: VariableKind, mut payload: &[u8]) -> anyhow::Result<Self> {
-        Ok(match kind {
+    /// Decodes a variable's payload as the kind with the ID `kind`. Kinds that aren't known
+    /// are kept as [`Self::Unknown`] with their ID and data as is, so a new kind of variable
+    /// still gets its data.
+    pub fn from_payload(kind: u32, payload: &[u8]) -> anyhow::Result<Self> {
+        let Some(known) = VariableKind::from_id(kind) else {
+            return Ok(Self::Unknown(kind, payload.to_vec()));
+        };
+
+        Ok(match known {
             VariableKind::Image => Self::Image(payload.to_vec()),
+            VariableKind::Equation => Self::Equation(read_sized(payload)?.to_vec()),
             VariableKind::String => {
-                let length = payload.read_u16::<LE>()?;
-                let str = String::from_utf8_lossy(&payload[..length as usize]);
+                let str = String::from_utf8_lossy(read_sized(payload)?);
                 Self::String(str.to_string())
             }
+            VariableKind::Program => Self::Program(read_sized(payload)?.to_vec()),
+            VariableKind::ProtectedProgram => Self::ProtectedProgram(read_sized(payload)?.to_vec()),
+            VariableKind::Picture => Self::Picture(read_sized(payload)?.to_vec()),
+            VariableKind::AppVar => Self::AppVar(read_sized(payload)?.to_vec()),
             VariableKind::App => Self::App(payload.to_vec()),
+            VariableKind::Unknown => Self::Unknown(kind, payload.to_vec()),
+        })
+    }
+
+    /// Wraps a variable's data, without the length prefix some kinds are sent with, in the
+    /// matching variant. Fails if the data isn't valid for the kind, or with
+    /// [`UnknownVariableKindError`] for [`VariableKind::Unknown`], which needs the real kind ID
+    /// to be sent.
+    pub fn from_data(kind: VariableKind, data: &[u8]) -> anyhow::Result<Self> {
+        Ok(match kind {
+            VariableKind::Image => Self::Image(data.to_vec()),
+            VariableKind::Equation => Self::Equation(data.to_vec()),
+            VariableKind::String => Self::String(String::from_utf8(data.to_vec())?),
+            VariableKind::Program => Self::Program(data.to_vec()),
+            VariableKind::ProtectedProgram => Self::ProtectedProgram(data.to_vec()),
+            VariableKind::Picture => Self::Picture(data.to_vec()),
+            VariableKind::AppVar => Self::AppVar(data.to_vec()),
+            VariableKind::App => Self::App(data.to_vec()),
+            VariableKind::Unknown => return Err(UnknownVariableKindError(kind as u32).into()),
         })
     }
 
+    /// The ID of the variable's kind, which for [`Self::Unknown`] is the one it was received
+    /// with.
+    pub fn kind_id(&self) -> u32 {
+        match self {
+            Self::Unknown(kind, _) => *kind,
+            contents => VariableKind::from(contents) as u32,
+        }
+    }
+
+    /// The variable's data without any length prefix, the reverse of [`Self::from_data`].
+    pub fn into_data(self) -> Vec<u8> {
+        match self {
+            Self::String(str) => str.into_bytes(),
+            Self::Image(data)
+            | Self::Equation(data)
+            | Self::Program(data)
+            | Self::ProtectedProgram(data)
+            | Self::Picture(data)
+            | Self::AppVar(data)
+            | Self::App(data)
+            | Self::Unknown(_, data) => data,
+        }
+    }
+
+    /// How long [`Self::into_payload`] would be, which is what the `Size` attribute holds.
+    pub fn payload_len(&self) -> usize {
+        match self {
+            Self::String(str) => 2 + str.len(),
+            Self::Equation(data)
+            | Self::Program(data)
+            | Self::ProtectedProgram(data)
+            | Self::Picture(data)
+            | Self::AppVar(data) => 2 + data.len(),
+            Self::Image(data) | Self::App(data) | Self::Unknown(_, data) => data.len(),
+        }
+    }
+
     pub fn into_payload(self) -> Vec<u8> {
         match self {
-            Self::String(str) => {
-                let mut payload = (str.len() as u16).to_le_bytes().to_vec();
-                payload.extend_from_slice(str.as_bytes());
-                payload
-            }
-            _ => todo!(),
+            Self::String(str) => sized(str.as_bytes()),
+            Self::Equation(data)
+            | Self::Program(data)
+            | Self::ProtectedProgram(data)
+            | Self::Picture(data)
+            | Self::AppVar(data) => sized(&data),
+            Self::Image(data) | Self::App(data) | Self::Unknown(_, data) => data,
+        }
+    }
+}
+
+/// A summary of the contents, like `String(4 bytes): Test` or `App(12288 bytes)`. Only strings
+/// show their contents.
+impl fmt::Display for VariableContents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = VariableKind::from(self);
+        match self {
+            Self::String(string) => write!(f, "{kind:?}({} bytes): {string}", string.len()),
+            Self::Image(data)
+            | Self::Equation(data)
+            | Self::Program(data)
+            | Self::ProtectedProgram(data)
+            | Self::Picture(data)
+            | Self::AppVar(data)
+            | Self::App(data) => write!(f, "{kind:?}({} bytes)", data.len()),
+            Self::Unknown(kind, data) => write!(f, "Unknown {kind:#010x}({} bytes)", data.len()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub struct UnknownVariableKindError(pub u32);
+impl fmt::Display for UnknownVariableKindError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown variable kind {:#010x}", self.0)
+    }
+}
+
+/// Reads the data of a variable stored with a little-endian length in front of it. The length
+/// doesn't count its own 2 bytes, so it's always the `Size` attribute minus 2. This is the same
+/// on the CE as on the 84+. Any bytes after the declared length are ignored.
+pub fn read_sized(mut payload: &[u8]) -> anyhow::Result<&[u8]> {
+    let length = payload.read_u16::<LE>()? as usize;
+    payload.get(..length).ok_or_else(|| {
+        ContentsTooShort {
+            declared: length,
+            received: payload.len(),
+        }
+        .into()
+    })
+}
+
+#[derive(Error, Debug)]
+#[error("variable contents are {received} bytes long but claim to have {declared}")]
+pub struct ContentsTooShort {
+    pub declared: usize,
+    pub received: usize,
+}
+
+fn sized(data: &[u8]) -> Vec<u8> {
+    let mut payload = (data.len() as u16).to_le_bytes().to_vec();
+    payload.extend_from_slice(data);
+    payload
+}
+
+impl VariableKind {
+    /// The kind with the given ID, or `None` if it isn't known. Unlike `from_repr`, this never
+    /// returns [`Self::Unknown`].
+    pub fn from_id(id: u32) -> Option<Self> {
+        Self::from_repr(id).filter(|kind| *kind != Self::Unknown)
+    }
+
+    /// The type ID used by the calculator and in TI files, which is the lowest byte of the kind.
+    pub fn file_type_byte(self) -> u8 {
+        self as u32 as u8
+    }
+
+    /// The kind with the given type ID, the reverse of [`Self::file_type_byte`].
+    pub fn from_file_type_byte(type_id: u8) -> Option<Self> {
+        Self::iter()
+            .filter(|kind| *kind != Self::Unknown)
+            .find(|kind| kind.file_type_byte() == type_id)
+    }
+
+    /// The extension of the TI file this kind of variable is saved as, without the dot.
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            Self::Image => "8ca",
+            Self::Equation => "8xy",
+            Self::String => "8xs",
+            Self::Program | Self::ProtectedProgram => "8xp",
+            Self::Picture => "8xi",
+            Self::AppVar => "8xv",
+            Self::App => "8ek",
+            Self::Unknown => "bin",
         }
     }
 }
 
 #[derive(Debug)]
 pub enum Screenshot {
-    Monochrome,             // 1 bit per pixel
+    /// 1 bit per pixel, a row at a time with the leftmost pixel in the highest bit. Set bits are
+    /// dark pixels. (84+ family except the CSE)
+    Monochrome(Vec<u8>),
     Grayscale,              // 4 bits per pixel (Nspire)
     Rgb(Box<[u16; 76800]>), // 16 bits per pixel (5 red, 6 green, 5 blue) (Nspire CX/84+CSE/83PCE/84+CE)
+    /// A screen in a layout we don't decode, left as the bytes the calculator sent. How to read
+    /// it depends on the model.
+    Raw(Vec<u8>),
+}
+
+/// Size of a full 320x240 RGB565 screen.
+pub const RGB_SCREEN_LENGTH: usize = 76800 * 2;
+
+impl Screenshot {
+    /// Reads a screen that was left as [`Self::Raw`] in the format `model`'s screen uses, if
+    /// it's the right length for it. Anything else is returned unchanged.
+    pub fn with_model(self, model: Model) -> Self {
+        match self {
+            Self::Raw(bytes) => match model.screen_dimensions() {
+                (width, height, 1) if bytes.len() == (width * height / 8) as usize => {
+                    Self::Monochrome(bytes)
+                }
+                _ => Self::Raw(bytes),
+            },
+            screenshot => screenshot,
+        }
+    }
+
+    /// Converts an RGB or monochrome screen to an image. Other formats aren't supported yet.
+    pub fn to_image(
+        &self,
+        width: u32,
+        height: u32,
+    ) -> Result<image::RgbImage, ScreenshotUnsupported> {
+        let pixels = match self {
+            Self::Rgb(pixels) => pixels,
+            Self::Monochrome(bytes) => return monochrome_image(bytes, width, height),
+            _ => return Err(ScreenshotUnsupported),
+        };
+
+        let mut img = image::RgbImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let Some(&pixel) = pixels.get((y * width + x) as usize) else {
+                    return Err(ScreenshotUnsupported);
+                };
+                let r = (pixel & 0b11111_000000_00000) >> 11;
+                let r = r as f32 / 31.0 * 255.0;
+
+                let g = (pixel & 0b00000_111111_00000) >> 5;
+                let g = g as f32 / 63.0 * 255.0;
+
+                let b = pixel & 0b00000_000000_11111;
+                let b = b as f32 / 31.0 * 255.0;
+
+                img.put_pixel(x, y, image::Rgb([r as u8, g as u8, b as u8]));
+            }
+        }
+
+        Ok(img)
+    }
+
+    /// The screen exactly as the calculator sent it, with RGB pixels as little-endian RGB565.
+    /// Useful for comparisons that converting to 8 bits per channel would throw off.
+    pub fn to_raw_bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Self::Rgb(pixels) => Some(
+                pixels
+                    .iter()
+                    .flat_map(|pixel| pixel.to_le_bytes())
+                    .collect(),
+            ),
+            Self::Raw(bytes) | Self::Monochrome(bytes) => Some(bytes.clone()),
+            Self::Grayscale => None,
+        }
+    }
+}
+
+fn monochrome_image(
+    bytes: &[u8],
+    width: u32,
+    height: u32,
+) -> Result<image::RgbImage, ScreenshotUnsupported> {
+    let row_length = width.div_ceil(8);
+    let mut img = image::RgbImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let Some(&byte) = bytes.get((y * row_length + x / 8) as usize) else {
+                return Err(ScreenshotUnsupported);
+            };
+            let dark = byte & (0x80 >> (x % 8)) != 0;
+            let value = if dark { 0x00 } else { 0xff };
+            img.put_pixel(x, y, image::Rgb([value; 3]));
+        }
+    }
+
+    Ok(img)
+}
+
+#[derive(Debug, Error)]
+#[error("calculator didn't send its screen contents, it might not support screenshots")]
+pub struct ScreenshotUnsupported;
+
+/// The colors a color picture's pixels index into, starting from 1. 0 is transparent.
+const PICTURE_PALETTE: [[u8; 3]; 15] = [
+    [0x00, 0x00, 0xff], // Blue
+    [0xff, 0x00, 0x00], // Red
+    [0x00, 0x00, 0x00], // Black
+    [0xff, 0x00, 0xff], // Magenta
+    [0x00, 0x9f, 0x00], // Green
+    [0xff, 0x8f, 0x20], // Orange
+    [0xb6, 0x20, 0x00], // Brown
+    [0x00, 0x00, 0x86], // Navy
+    [0x00, 0x93, 0xff], // Light blue
+    [0xff, 0xff, 0x00], // Yellow
+    [0xff, 0xff, 0xff], // White
+    [0xe7, 0xe3, 0xe7], // Light gray
+    [0xc7, 0xc3, 0xc7], // Medium gray
+    [0x8f, 0x8b, 0x8f], // Gray
+    [0x51, 0x55, 0x51], // Dark gray
+];
+
+#[derive(Debug, Error)]
+#[error("unrecognized picture format ({0} bytes)")]
+pub struct UnknownPictureFormat(pub usize);
+
+/// Decodes the data of a `Picture` variable. The 84+ CSE shares a product ID with monochrome
+/// models, so the format is told apart by its length instead of by model.
+pub fn decode_picture(data: &[u8]) -> Result<image::RgbImage, UnknownPictureFormat> {
+    match data.len() {
+        // 96x63, 1 bit per pixel with the leftmost pixel in the highest bit
+        756 => Ok(image::RgbImage::from_fn(96, 63, |x, y| {
+            let byte = data[(y * 12 + x / 8) as usize];
+            if byte & (0x80 >> (x % 8)) != 0 {
+                image::Rgb([0, 0, 0])
+            } else {
+                image::Rgb([0xff, 0xff, 0xff])
+            }
+        })),
+        // 265x165, 4 bits per pixel with the leftmost pixel in the high nibble. Each row is
+        // padded to 133 bytes.
+        21945 => Ok(image::RgbImage::from_fn(265, 165, |x, y| {
+            let byte = data[(y * 133 + x / 2) as usize];
+            let index = if x % 2 == 0 { byte >> 4 } else { byte & 0x0f };
+            match index {
+                0 => image::Rgb([0xff, 0xff, 0xff]),
+                index => image::Rgb(PICTURE_PALETTE[index as usize - 1]),
+            }
+        })),
+        length => Err(UnknownPictureFormat(length)),
+    }
 }
 
 #[repr(u16)]
@@ -139,22 +769,160 @@ pub enum Screenshot {
 #[strum_discriminants(name(ParameterKind))]
 #[strum_discriminants(derive(FromRepr))]
 pub enum Parameter {
+    /// The product name, e.g. "TI-84 Plus CE". This is fixed by the OS, there's no
+    /// user-settable device name.
     Name(String) = 0x0002,
+    /// The board revision. How revisions are numbered isn't documented.
+    HardwareVersion(u16) = 0x0004,
+    BootVersion(FirmwareVersion) = 0x0009,
+    OsMode(OsMode) = 0x000a,
+    OsVersion(FirmwareVersion) = 0x000b,
     TotalAppPages(u64) = 0x0012,
     FreeAppPages(u64) = 0x0013,
     ScreenWidth(u16) = 0x001e,
     ScreenHeight(u16) = 0x001f,
     ScreenContents(Screenshot) = 0x0022,
+    ClockOn(bool) = 0x0024,
+    /// Seconds since midnight, January 1st 1997.
     Clock(u32) = 0x25,
+    DateFormat(DateFormat) = 0x0027,
+    Clock24Hour(bool) = 0x0028,
+    Battery(BatteryStatus) = 0x002d,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum DateFormat {
+    MonthDayYear = 1,
+    DayMonthYear = 2,
+    YearMonthDay = 3,
+}
+
+/// The version of the OS or boot code, shown as e.g. "2.55" or "5.3.0.0037".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FirmwareVersion {
+    pub major: u8,
+    pub minor: u8,
+    /// Only sent by the CE, 0 otherwise.
+    pub patch: u8,
+    /// Only sent by the CE, 0 otherwise.
+    pub build: u16,
+}
+
+impl FirmwareVersion {
+    /// Decodes a version parameter: an unused byte, the major and minor version and, from the
+    /// CE, the patch and build numbers.
+    fn from_payload(mut payload: &[u8]) -> io::Result<Self> {
+        payload.read_u8()?;
+        let major = payload.read_u8()?;
+        let minor = payload.read_u8()?;
+        let patch = payload.read_u8().unwrap_or_default();
+        let build = payload.read_u16::<BE>().unwrap_or_default();
+
+        Ok(Self {
+            major,
+            minor,
+            patch,
+            build,
+        })
+    }
+
+    fn into_payload(self) -> Vec<u8> {
+        let mut payload = vec![0, self.major, self.minor, self.patch];
+        payload.extend_from_slice(&self.build.to_be_bytes());
+        payload
+    }
+}
+
+impl fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.patch == 0 && self.build == 0 {
+            write!(f, "{}.{:02}", self.major, self.minor)
+        } else {
+            write!(
+                f,
+                "{}.{}.{}.{:04}",
+                self.major, self.minor, self.patch, self.build
+            )
+        }
+    }
+}
+
+/// What the calculator is running. OSes can only be sent while the boot code is waiting for
+/// one, which it does after the OS asks for a transfer or is missing or invalid.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum OsMode {
+    Boot = 1,
+    Os = 2,
+}
+
+/// See [`Calculator::boot_info`](crate::Calculator::boot_info).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootInfo {
+    pub boot_version: FirmwareVersion,
+    /// `None` if there's no valid OS installed.
+    pub os_version: Option<FirmwareVersion>,
+    pub mode: OsMode,
+}
+
+/// See [`Calculator::device_info`](crate::Calculator::device_info).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub name: Option<String>,
+    pub hardware_version: Option<u16>,
+    pub os_version: Option<FirmwareVersion>,
+}
+
+/// Everything about the clock that can be read and changed, see
+/// [`Calculator::clock_settings`](crate::Calculator::clock_settings).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockSettings {
+    pub enabled: bool,
+    /// Seconds since midnight, January 1st 1997.
+    pub time: u32,
+    pub date_format: DateFormat,
+    pub twenty_four_hour: bool,
+}
+
+/// The calculator only reports whether its batteries are good enough for things like OS
+/// transfers, not an actual charge level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryStatus {
+    Good,
+    Low,
 }
 
 #[derive(Debug, Error)]
-#[error("invalid parameter payload received")]
-pub struct InvalidParameterPayload;
+pub enum InvalidParameterPayload {
+    #[error("wrong length for {kind:?} parameter: expected {expected} bytes, received {received}")]
+    WrongLength {
+        kind: ParameterKind,
+        expected: usize,
+        received: usize,
+    },
+    #[error("invalid value {value} for {kind:?} parameter")]
+    InvalidValue { kind: ParameterKind, value: u8 },
+    #[error("invalid parameter payload received")]
+    Io(#[from] io::Error),
+}
 
-impl From<io::Error> for InvalidParameterPayload {
-    fn from(_value: io::Error) -> Self {
-        Self // TODO properly convert the error to be more specific
+impl ParameterKind {
+    /// How many bytes of data this parameter has, or `None` if it can be any length.
+    pub fn data_length(self) -> Option<usize> {
+        match self {
+            Self::TotalAppPages | Self::FreeAppPages => Some(8),
+            Self::ScreenWidth | Self::ScreenHeight => Some(2),
+            Self::Clock => Some(4),
+            Self::ClockOn | Self::OsMode | Self::DateFormat | Self::Clock24Hour | Self::Battery => {
+                Some(1)
+            }
+            Self::Name
+            | Self::HardwareVersion
+            | Self::BootVersion
+            | Self::OsVersion
+            | Self::ScreenContents => None,
+        }
     }
 }
 
@@ -163,19 +931,239 @@ impl Parameter {
         kind: ParameterKind,
         mut payload: &[u8],
     ) -> Result<Self, InvalidParameterPayload> {
+        if let Some(expected) = kind.data_length() {
+            if payload.len() != expected {
+                return Err(InvalidParameterPayload::WrongLength {
+                    kind,
+                    expected,
+                    received: payload.len(),
+                });
+            }
+        }
+
         Ok(match kind {
-            ParameterKind::Name => Self::Name(String::from_utf8_lossy(payload).into_owned()),
+            // There's no length prefix, but the string is NUL-terminated
+            ParameterKind::Name => Self::Name(
+                String::from_utf8_lossy(payload)
+                    .trim_end_matches('\0')
+                    .to_owned(),
+            ),
+            // Sent as either one or two bytes depending on the model
+            ParameterKind::HardwareVersion => Self::HardwareVersion(match payload.len() {
+                1 => payload[0] as u16,
+                _ => payload.read_u16::<BE>()?,
+            }),
+            ParameterKind::BootVersion => {
+                Self::BootVersion(FirmwareVersion::from_payload(payload)?)
+            }
+            ParameterKind::OsMode => Self::OsMode(OsMode::from_repr(payload[0]).ok_or(
+                InvalidParameterPayload::InvalidValue {
+                    kind,
+                    value: payload[0],
+                },
+            )?),
+            ParameterKind::OsVersion => Self::OsVersion(FirmwareVersion::from_payload(payload)?),
             ParameterKind::TotalAppPages => Self::TotalAppPages(payload.read_u64::<BE>()?),
             ParameterKind::FreeAppPages => Self::FreeAppPages(payload.read_u64::<BE>()?),
-            ParameterKind::ScreenWidth => Self::ScreenWidth(u16_from_bytes(&payload[0..2])),
-            ParameterKind::ScreenHeight => Self::ScreenHeight(u16_from_bytes(&payload[0..2])),
-            ParameterKind::ScreenContents => Self::ScreenContents(Screenshot::Rgb(Box::new({
-                let a: Vec<u16> = payload.chunks_exact(2).map(LE::read_u16).collect();
-                a.try_into().unwrap()
-            }))),
-            ParameterKind::Clock => Self::Clock(u32_from_bytes(&payload[0..4])),
+            ParameterKind::ScreenWidth => Self::ScreenWidth(u16_from_bytes(payload)),
+            ParameterKind::ScreenHeight => Self::ScreenHeight(u16_from_bytes(payload)),
+            // Anything other than a full RGB screen is passed on undecoded rather than guessed at
+            ParameterKind::ScreenContents if payload.len() == RGB_SCREEN_LENGTH => {
+                let pixels: Box<[u16]> = payload.chunks_exact(2).map(LE::read_u16).collect();
+                Self::ScreenContents(Screenshot::Rgb(pixels.try_into().unwrap()))
+            }
+            ParameterKind::ScreenContents => {
+                Self::ScreenContents(Screenshot::Raw(payload.to_vec()))
+            }
+            ParameterKind::ClockOn => Self::ClockOn(payload[0] != 0),
+            ParameterKind::Clock => Self::Clock(u32_from_bytes(payload)),
+            ParameterKind::DateFormat => Self::DateFormat(
+                DateFormat::from_repr(payload[0]).ok_or(InvalidParameterPayload::InvalidValue {
+                    kind,
+                    value: payload[0],
+                })?,
+            ),
+            ParameterKind::Clock24Hour => Self::Clock24Hour(payload[0] != 0),
+            ParameterKind::Battery => Self::Battery(match payload[0] {
+                1 => BatteryStatus::Low,
+                _ => BatteryStatus::Good,
+            }),
         })
     }
+
+    pub fn into_payload(self) -> Vec<u8> {
+        match self {
+            Self::Name(name) => {
+                let mut payload = name.into_bytes();
+                payload.push(0);
+                payload
+            }
+            Self::HardwareVersion(version) => version.to_be_bytes().to_vec(),
+            Self::BootVersion(version) | Self::OsVersion(version) => version.into_payload(),
+            Self::OsMode(mode) => vec![mode as u8],
+            Self::TotalAppPages(pages) | Self::FreeAppPages(pages) => pages.to_be_bytes().to_vec(),
+            Self::ScreenWidth(size) | Self::ScreenHeight(size) => size.to_be_bytes().to_vec(),
+            Self::ScreenContents(screenshot) => screenshot.to_raw_bytes().unwrap_or_default(),
+            Self::Clock(time) => time.to_be_bytes().to_vec(),
+            Self::DateFormat(format) => vec![format as u8],
+            Self::ClockOn(flag) | Self::Clock24Hour(flag) => vec![flag as u8],
+            Self::Battery(status) => vec![(status == BatteryStatus::Low) as u8],
+        }
+    }
+}
+
+/// The parameters sent back by the calculator, with a getter for each kind. Getters return
+/// `None` if the parameter wasn't requested or the calculator marked it as invalid.
+#[derive(Debug, Default)]
+pub struct ParameterSet {
+    pub parameters: Vec<Parameter>,
+    /// The parameters the calculator marked as invalid instead of sending.
+    pub unavailable: Vec<Unavailable>,
+}
+
+/// A parameter or attribute the calculator marked as invalid instead of sending, with the code
+/// it gave. Nothing documents what the codes mean, so they're kept as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Unavailable {
+    pub id: u16,
+    pub code: u8,
+}
+
+impl ParameterSet {
+    pub fn name(&self) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::Name(name) => Some(name.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn hardware_version(&self) -> Option<u16> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::HardwareVersion(version) => Some(*version),
+                _ => None,
+            })
+    }
+
+    pub fn boot_version(&self) -> Option<FirmwareVersion> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::BootVersion(version) => Some(*version),
+                _ => None,
+            })
+    }
+
+    pub fn os_mode(&self) -> Option<OsMode> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::OsMode(mode) => Some(*mode),
+                _ => None,
+            })
+    }
+
+    pub fn os_version(&self) -> Option<FirmwareVersion> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::OsVersion(version) => Some(*version),
+                _ => None,
+            })
+    }
+
+    pub fn total_app_pages(&self) -> Option<u64> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::TotalAppPages(pages) => Some(*pages),
+                _ => None,
+            })
+    }
+
+    pub fn free_app_pages(&self) -> Option<u64> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::FreeAppPages(pages) => Some(*pages),
+                _ => None,
+            })
+    }
+
+    pub fn screen_width(&self) -> Option<u16> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::ScreenWidth(width) => Some(*width),
+                _ => None,
+            })
+    }
+
+    pub fn screen_height(&self) -> Option<u16> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::ScreenHeight(height) => Some(*height),
+                _ => None,
+            })
+    }
+
+    pub fn screen_contents(&self) -> Option<&Screenshot> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::ScreenContents(screenshot) => Some(screenshot),
+                _ => None,
+            })
+    }
+
+    pub fn clock(&self) -> Option<u32> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::Clock(clock) => Some(*clock),
+                _ => None,
+            })
+    }
+
+    pub fn clock_on(&self) -> Option<bool> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::ClockOn(on) => Some(*on),
+                _ => None,
+            })
+    }
+
+    pub fn date_format(&self) -> Option<DateFormat> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::DateFormat(format) => Some(*format),
+                _ => None,
+            })
+    }
+
+    pub fn clock_24_hour(&self) -> Option<bool> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::Clock24Hour(twenty_four_hour) => Some(*twenty_four_hour),
+                _ => None,
+            })
+    }
+
+    pub fn battery(&self) -> Option<BatteryStatus> {
+        self.parameters
+            .iter()
+            .find_map(|parameter| match parameter {
+                Parameter::Battery(status) => Some(*status),
+                _ => None,
+            })
+    }
 }
 
 #[derive(Error, Debug)]
@@ -185,3 +1173,345 @@ impl fmt::Display for UnknownParameterKindError {
         write!(f, "unknown parameter kind {}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(attributes: Vec<VariableAttribute>) -> Variable {
+        Variable {
+            name: VariableName::from("A"),
+            attributes,
+            unavailable: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sized_too_short() {
+        let err = read_sized(&[5, 0, b'T', b'e', b's', b't']).unwrap_err();
+        let err = err.downcast_ref::<ContentsTooShort>().unwrap();
+        assert_eq!((err.declared, err.received), (5, 4));
+    }
+
+    #[test]
+    fn sized_exact_length() {
+        assert_eq!(
+            read_sized(&[4, 0, b'T', b'e', b's', b't']).unwrap(),
+            b"Test"
+        );
+    }
+
+    #[test]
+    fn applied_attributes_match() {
+        let sent = header(vec![
+            VariableAttribute::Archived(true),
+            VariableAttribute::Locked(false),
+        ]);
+        let stored = header(vec![
+            VariableAttribute::Locked(false),
+            VariableAttribute::Archived(true),
+        ]);
+
+        assert!(sent.check_applied(&stored).is_ok());
+    }
+
+    #[test]
+    fn unapplied_attribute_is_reported() {
+        let sent = header(vec![VariableAttribute::Archived(true)]);
+        let stored = header(vec![VariableAttribute::Archived(false)]);
+
+        let err = sent.check_applied(&stored).unwrap_err();
+        assert_eq!(err.attribute, VariableAttributeKind::Archived);
+        assert_eq!(err.stored, Some(false));
+    }
+
+    #[test]
+    fn variables_sort_by_name_kind_then_location() {
+        let variable = |name: &str, kind: u32, archived: bool| Variable {
+            name: VariableName::from(name),
+            attributes: vec![
+                VariableAttribute::Kind(kind),
+                VariableAttribute::Archived2(archived),
+            ],
+            unavailable: Vec::new(),
+        };
+        let mut variables = vec![
+            variable("B", 5, false),
+            variable("A", 6, true),
+            variable("A", 5, true),
+            variable("A", 5, false),
+        ];
+        sort_variables(&mut variables);
+
+        let sorted: Vec<_> = variables
+            .iter()
+            .map(|var| (var.name.to_string(), var.kind(), var.location()))
+            .collect();
+        assert_eq!(
+            sorted,
+            [
+                ("A".to_string(), Some(5), Some(StorageLocation::Ram)),
+                ("A".to_string(), Some(5), Some(StorageLocation::Archive)),
+                ("A".to_string(), Some(6), Some(StorageLocation::Archive)),
+                ("B".to_string(), Some(5), Some(StorageLocation::Ram)),
+            ]
+        );
+    }
+
+    #[test]
+    fn attribute_lengths() {
+        // Unknown attributes keep whatever data they have, along with their ID
+        match VariableAttribute::from_id(0x42, &[1, 2, 3]).unwrap() {
+            VariableAttribute::Unknown(id, data) => {
+                assert_eq!(id, 0x42);
+                assert_eq!(data, [1, 2, 3]);
+            }
+            attribute => panic!("unexpected {attribute:?}"),
+        }
+
+        let err = VariableAttribute::from_id(0x08, &[0, 1]).unwrap_err();
+        let err = err.downcast_ref::<WrongAttributeLength>().unwrap();
+        assert!(matches!(err.kind, VariableAttributeKind::Version));
+        assert_eq!((err.expected, err.received), (1, 2));
+    }
+
+    #[test]
+    fn firmware_versions() {
+        let version = FirmwareVersion::from_payload(&[0, 2, 55]).unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (2, 55, 0));
+        assert_eq!(version.to_string(), "2.55");
+
+        let version = FirmwareVersion::from_payload(&[0, 5, 3, 0, 0, 37]).unwrap();
+        assert_eq!(version.build, 37);
+        assert_eq!(version.to_string(), "5.3.0.0037");
+
+        assert!(FirmwareVersion::from_payload(&[0, 5]).is_err());
+    }
+
+    #[test]
+    fn hardware_version_lengths() {
+        for payload in [&[0x07][..], &[0x00, 0x07][..]] {
+            let parameter = Parameter::from_payload(ParameterKind::HardwareVersion, payload);
+            assert!(matches!(parameter, Ok(Parameter::HardwareVersion(7))));
+        }
+    }
+
+    #[test]
+    fn monochrome_screenshots() {
+        let mut bytes = vec![0; 768];
+        // The top left pixel and the last one on the first row
+        bytes[0] = 0x80;
+        bytes[11] = 0x01;
+        let screenshot = Screenshot::Raw(bytes.clone()).with_model(Model::Ti84Plus);
+        assert!(matches!(&screenshot, Screenshot::Monochrome(data) if *data == bytes));
+
+        let image = screenshot.to_image(96, 64).unwrap();
+        assert_eq!(image.get_pixel(0, 0).0, [0; 3]);
+        assert_eq!(image.get_pixel(1, 0).0, [0xff; 3]);
+        assert_eq!(image.get_pixel(95, 0).0, [0; 3]);
+        assert_eq!(image.get_pixel(0, 1).0, [0xff; 3]);
+        assert_eq!(screenshot.to_raw_bytes(), Some(bytes));
+    }
+
+    #[test]
+    fn unexpected_screen_length_stays_raw() {
+        let screenshot = Screenshot::Raw(vec![0; 100]).with_model(Model::Ti84Plus);
+        assert!(matches!(&screenshot, Screenshot::Raw(data) if data.len() == 100));
+        assert!(screenshot.to_image(96, 64).is_err());
+
+        let screenshot = Screenshot::Raw(vec![0; 768]).with_model(Model::Ti84PlusCe);
+        assert!(matches!(screenshot, Screenshot::Raw(_)));
+    }
+
+    #[test]
+    fn file_types() {
+        let expected = [
+            (VariableKind::Image, 0x1a, "8ca"),
+            (VariableKind::Equation, 0x03, "8xy"),
+            (VariableKind::String, 0x04, "8xs"),
+            (VariableKind::Program, 0x05, "8xp"),
+            (VariableKind::ProtectedProgram, 0x06, "8xp"),
+            (VariableKind::Picture, 0x07, "8xi"),
+            (VariableKind::AppVar, 0x15, "8xv"),
+            (VariableKind::App, 0x24, "8ek"),
+            (VariableKind::Unknown, 0xff, "bin"),
+        ];
+        for (kind, type_id, extension) in expected {
+            assert_eq!(kind.file_type_byte(), type_id, "{kind:?}");
+            assert_eq!(kind.file_extension(), extension, "{kind:?}");
+        }
+
+        assert_eq!(
+            VariableKind::from_file_type_byte(0x05),
+            Some(VariableKind::Program)
+        );
+        assert_eq!(VariableKind::from_file_type_byte(0xff), None);
+    }
+
+    #[test]
+    fn clock_with_wrong_length_is_error() {
+        for payload in [&[0, 0, 1][..], &[0, 0, 0, 0, 1][..]] {
+            let err = Parameter::from_payload(ParameterKind::Clock, payload).unwrap_err();
+            assert!(matches!(
+                err,
+                InvalidParameterPayload::WrongLength {
+                    kind: ParameterKind::Clock,
+                    expected: 4,
+                    received,
+                } if received == payload.len()
+            ));
+        }
+    }
+
+    #[test]
+    fn name_round_trip() {
+        let payload = b"TI-84 Plus CE\0";
+        let parameter = Parameter::from_payload(ParameterKind::Name, payload).unwrap();
+        assert!(matches!(&parameter, Parameter::Name(name) if name == "TI-84 Plus CE"));
+        assert_eq!(parameter.into_payload(), payload);
+    }
+
+    #[test]
+    fn battery_levels() {
+        for (payload, expected) in [(0, BatteryStatus::Good), (1, BatteryStatus::Low)] {
+            let parameter = Parameter::from_payload(ParameterKind::Battery, &[payload]).unwrap();
+            let parameters = ParameterSet {
+                parameters: vec![parameter],
+                unavailable: Vec::new(),
+            };
+            assert_eq!(parameters.battery(), Some(expected));
+        }
+
+        assert!(Parameter::from_payload(ParameterKind::Battery, &[]).is_err());
+    }
+
+    #[test]
+    fn rgb_screen_raw_bytes() {
+        let bytes: Vec<u8> = (0..RGB_SCREEN_LENGTH).map(|i| (i % 253) as u8).collect();
+        let Parameter::ScreenContents(screenshot) =
+            Parameter::from_payload(ParameterKind::ScreenContents, &bytes).unwrap()
+        else {
+            panic!("not a screenshot");
+        };
+        let Screenshot::Rgb(pixels) = &screenshot else {
+            panic!("not an RGB screenshot");
+        };
+        assert_eq!(pixels[1], 0x0302);
+
+        assert_eq!(screenshot.to_raw_bytes(), Some(bytes));
+    }
+
+    #[test]
+    fn clock_parameters_round_trip() {
+        let payloads: [(ParameterKind, &[u8]); 4] = [
+            (ParameterKind::ClockOn, &[1]),
+            (ParameterKind::Clock, &[0x2c, 0x1e, 0x3a, 0x80]),
+            (ParameterKind::DateFormat, &[3]),
+            (ParameterKind::Clock24Hour, &[0]),
+        ];
+        let parameters = ParameterSet {
+            parameters: payloads
+                .iter()
+                .map(|(kind, payload)| Parameter::from_payload(*kind, payload).unwrap())
+                .collect(),
+            unavailable: Vec::new(),
+        };
+
+        assert_eq!(parameters.clock_on(), Some(true));
+        assert_eq!(parameters.clock(), Some(0x2c1e3a80));
+        assert_eq!(parameters.date_format(), Some(DateFormat::YearMonthDay));
+        assert_eq!(parameters.clock_24_hour(), Some(false));
+
+        for (parameter, (kind, payload)) in parameters.parameters.into_iter().zip(payloads) {
+            assert_eq!(parameter.into_payload(), payload, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn display() {
+        let string = Variable {
+            name: VariableName::from("Str1"),
+            attributes: vec![
+                VariableAttribute::Kind(VariableKind::String as u32),
+                VariableAttribute::Size(6),
+                VariableAttribute::Archived(true),
+            ],
+            unavailable: Vec::new(),
+        };
+        assert_eq!(string.to_string(), "Str1 (String, 6 bytes, archived)");
+        let contents = VariableContents::String("Test".to_string());
+        assert_eq!(contents.to_string(), "String(4 bytes): Test");
+
+        let app = Variable {
+            name: VariableName::from("Transfrm"),
+            attributes: vec![
+                VariableAttribute::Kind(VariableKind::App as u32),
+                VariableAttribute::Size(12288),
+                VariableAttribute::Locked(false),
+            ],
+            unavailable: Vec::new(),
+        };
+        assert_eq!(app.to_string(), "Transfrm (App, 12288 bytes)");
+        let contents = VariableContents::App(vec![0; 12288]);
+        assert_eq!(contents.to_string(), "App(12288 bytes)");
+    }
+
+    #[test]
+    fn boot_version() {
+        let parameter = Parameter::from_payload(ParameterKind::BootVersion, &[0, 5, 0, 0, 0, 43]);
+        let parameters = ParameterSet {
+            parameters: vec![parameter.unwrap()],
+            unavailable: Vec::new(),
+        };
+
+        assert_eq!(
+            parameters.boot_version(),
+            Some(FirmwareVersion {
+                major: 5,
+                minor: 0,
+                patch: 0,
+                build: 43,
+            })
+        );
+        assert_eq!(parameters.boot_version().unwrap().to_string(), "5.0.0.0043");
+    }
+
+    #[test]
+    fn equation_round_trip() {
+        // 2X+1
+        let payload = [4, 0, 0x32, 0x58, 0x70, 0x31];
+        let contents = VariableContents::from_payload(VariableKind::Equation as u32, &payload);
+        let contents = contents.unwrap();
+        assert!(matches!(&contents, VariableContents::Equation(tokens) if tokens == &payload[2..]));
+
+        assert_eq!(contents.payload_len(), payload.len());
+        assert_eq!(contents.into_payload(), payload);
+    }
+
+    #[test]
+    fn ce_string() {
+        // Str1 as a CE sends it, with its Size attribute of 7
+        let payload = [5, 0, b'H', b'E', b'L', b'L', b'O'];
+        let contents = VariableContents::from_payload(VariableKind::String as u32, &payload);
+        let contents = contents.unwrap();
+        assert!(matches!(&contents, VariableContents::String(string) if string == "HELLO"));
+
+        assert_eq!(contents.payload_len(), 7);
+        assert_eq!(contents.into_payload(), payload);
+    }
+
+    #[test]
+    fn unknown_kind_keeps_raw_bytes() {
+        // A list, which has no variant of its own
+        let contents = VariableContents::from_payload(0xf0070001, &[1, 0, 0x42]).unwrap();
+        assert!(matches!(
+            &contents,
+            VariableContents::Unknown(0xf0070001, data) if data == &[1, 0, 0x42]
+        ));
+        assert_eq!(contents.kind_id(), 0xf0070001);
+        assert_eq!(contents.into_payload(), [1, 0, 0x42]);
+
+        let err = VariableContents::from_data(VariableKind::Unknown, &[]).unwrap_err();
+        assert!(err.is::<UnknownVariableKindError>());
+    }
+}
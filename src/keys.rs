@@ -0,0 +1,88 @@
+use thiserror::Error;
+
+/// A key press sent to the calculator for remote control. These are the same codes the OS's
+/// `GetKey` routine returns (the `k*` equates in `ti83plus.inc`), sent little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCode(pub u16);
+
+impl KeyCode {
+    pub const RIGHT: Self = Self(0x01);
+    pub const LEFT: Self = Self(0x02);
+    pub const UP: Self = Self(0x03);
+    pub const DOWN: Self = Self(0x04);
+    pub const ENTER: Self = Self(0x05);
+    pub const CLEAR: Self = Self(0x09);
+    pub const DEL: Self = Self(0x0a);
+    pub const QUIT: Self = Self(0x40);
+    pub const GRAPH: Self = Self(0x44);
+    pub const MODE: Self = Self(0x45);
+    pub const WINDOW: Self = Self(0x48);
+    pub const Y_EQUALS: Self = Self(0x49);
+    pub const TABLE: Self = Self(0x4a);
+    pub const ADD: Self = Self(0x80);
+    pub const SUBTRACT: Self = Self(0x81);
+    pub const MULTIPLY: Self = Self(0x82);
+    pub const DIVIDE: Self = Self(0x83);
+    pub const POWER: Self = Self(0x84);
+    pub const LEFT_PAREN: Self = Self(0x85);
+    pub const RIGHT_PAREN: Self = Self(0x86);
+    pub const STORE: Self = Self(0x8a);
+    pub const COMMA: Self = Self(0x8b);
+    pub const NEGATE: Self = Self(0x8c);
+    pub const DECIMAL_POINT: Self = Self(0x8d);
+    pub const SPACE: Self = Self(0x99);
+
+    /// The key for a digit from 0 to 9.
+    pub fn digit(digit: u8) -> Option<Self> {
+        (digit <= 9).then(|| Self(0x8e + digit as u16))
+    }
+
+    /// The key for an uppercase letter from A to Z, as typed in alpha mode.
+    pub fn letter(letter: char) -> Option<Self> {
+        letter
+            .is_ascii_uppercase()
+            .then(|| Self(0x9a + (letter as u16 - 'A' as u16)))
+    }
+
+    /// The key that types `c`, or `None` if there isn't a single key for it. Letters have to
+    /// be uppercase, and `-` is subtraction rather than negation.
+    pub fn for_char(c: char) -> Option<Self> {
+        Some(match c {
+            '0'..='9' => return Self::digit(c as u8 - b'0'),
+            'A'..='Z' => return Self::letter(c),
+            ' ' => Self::SPACE,
+            '\n' => Self::ENTER,
+            '+' => Self::ADD,
+            '-' => Self::SUBTRACT,
+            '*' => Self::MULTIPLY,
+            '/' => Self::DIVIDE,
+            '^' => Self::POWER,
+            '(' => Self::LEFT_PAREN,
+            ')' => Self::RIGHT_PAREN,
+            '→' => Self::STORE,
+            ',' => Self::COMMA,
+            '.' => Self::DECIMAL_POINT,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("there's no key for {0:?}")]
+pub struct NoKeyForChar(pub char);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn characters() {
+        let keys: Vec<_> = "AB".chars().map(KeyCode::for_char).collect();
+        assert_eq!(keys, [Some(KeyCode(0x9a)), Some(KeyCode(0x9b))]);
+
+        assert_eq!(KeyCode::for_char('Z'), Some(KeyCode(0xb3)));
+        assert_eq!(KeyCode::for_char('7'), Some(KeyCode(0x95)));
+        assert_eq!(KeyCode::for_char('a'), None);
+        assert_eq!(KeyCode::for_char('!'), None);
+    }
+}
@@ -0,0 +1,178 @@
+use std::{
+    io::{self, Read},
+    time::Duration,
+};
+
+use byteorder::{ReadBytesExt, LE};
+use rusb::{DeviceHandle, GlobalContext};
+
+use crate::{
+    dusb::{Variable, VariableAttribute, VariableName},
+    model::{Model, UnsupportedProtocol},
+    packet::dbus::{DbusCommand, DbusPacket, PC_TI83},
+    transfer::{find_interface, TransferError},
+};
+
+/// Used in place of a variable type to request the directory listing.
+const TI83_DIRECTORY: u8 = 0x19;
+
+/// A calculator connected through a SilverLink cable, which uses the older link protocol
+/// instead of DUSB.
+pub struct LegacyCalculator {
+    pub device: DeviceHandle<GlobalContext>,
+    pub timeout: Duration,
+    /// The ID packets to the calculator start with, which depends on the model.
+    machine_id: u8,
+    read_endpoint: u8,
+    write_endpoint: u8,
+    buffer: Vec<u8>,
+}
+
+impl LegacyCalculator {
+    /// Claims the cable's interface, which is the first one with a pair of bulk endpoints
+    /// unless `interface` is given, like
+    /// [`CalculatorBuilder::interface`](crate::CalculatorBuilder::interface). Fails with
+    /// [`UnsupportedProtocol`] for models that don't use the legacy protocol.
+    pub fn new(
+        device: DeviceHandle<GlobalContext>,
+        model: Model,
+        interface: Option<u8>,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let machine_id = machine_id(model)?;
+        let interface = find_interface(&device.device(), interface)?;
+        device.claim_interface(interface.number)?;
+
+        Ok(Self {
+            device,
+            timeout,
+            machine_id,
+            read_endpoint: interface.read_endpoint,
+            write_endpoint: interface.write_endpoint,
+            buffer: Vec::new(),
+        })
+    }
+
+    pub fn send(&mut self, packet: DbusPacket) -> anyhow::Result<()> {
+        println!("PC->TI: Sending legacy packet {:?}", packet.command);
+        self.device
+            .write_bulk(self.write_endpoint, &packet.into_bytes(), self.timeout)
+            .map_err(TransferError::from)?;
+        Ok(())
+    }
+
+    pub fn receive(&mut self) -> anyhow::Result<DbusPacket> {
+        DbusPacket::receive(self)
+    }
+
+    fn acknowledge(&mut self) -> anyhow::Result<()> {
+        self.send(DbusPacket::new(
+            self.machine_id,
+            DbusCommand::Acknowledge,
+            Vec::new(),
+        ))
+    }
+
+    /// Lists every variable on the calculator. Only the `Size` and `Kind` attributes are
+    /// known, with the kind given in the same form as DUSB so it works with `VariableKind`.
+    pub fn request_directory(&mut self) -> anyhow::Result<Vec<Variable>> {
+        let mut request = 0u16.to_le_bytes().to_vec();
+        request.push(TI83_DIRECTORY);
+        request.extend_from_slice(&[0; 8]);
+        self.send(DbusPacket::new(
+            self.machine_id,
+            DbusCommand::Request,
+            request,
+        ))?;
+        self.receive()?.expect(DbusCommand::Acknowledge)?;
+
+        // The amount of free memory is sent before the variables
+        self.receive()?.expect(DbusCommand::Data)?;
+        self.acknowledge()?;
+
+        let mut variables = Vec::new();
+        loop {
+            let packet = self.receive()?;
+            // Unlike the headers, the end of the listing isn't acknowledged
+            if packet.command == DbusCommand::EndOfTransmission {
+                return Ok(variables);
+            }
+
+            let packet = packet.expect(DbusCommand::VariableHeader)?;
+            self.acknowledge()?;
+            variables.push(decode_variable_header(&packet.data)?);
+        }
+    }
+}
+
+/// The machine ID the computer uses when talking to `model`.
+fn machine_id(model: Model) -> Result<u8, UnsupportedProtocol> {
+    match model {
+        Model::Ti83 => Ok(PC_TI83),
+        model => Err(UnsupportedProtocol {
+            model,
+            protocol: model.protocol(),
+        }),
+    }
+}
+
+fn decode_variable_header(mut data: &[u8]) -> anyhow::Result<Variable> {
+    let size = data.read_u16::<LE>()?;
+    let type_id = data.read_u8()?;
+    let mut name = [0; 8];
+    data.read_exact(&mut name)?;
+    let name = name.split(|byte| *byte == 0).next().unwrap_or_default();
+
+    Ok(Variable {
+        name: VariableName(name.to_vec()),
+        attributes: vec![
+            VariableAttribute::Size(size as u32),
+            VariableAttribute::Kind(u32::from_be_bytes([0xf0, 0x07, 0x00, type_id])),
+        ],
+        unavailable: Vec::new(),
+    })
+}
+
+impl Read for LegacyCalculator {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffer.is_empty() {
+            self.buffer.resize(64, 0);
+            let bytes_read = self
+                .device
+                .read_bulk(self.read_endpoint, &mut self.buffer, self.timeout)
+                .map_err(TransferError::from)?;
+            self.buffer.truncate(bytes_read);
+        }
+
+        let bytes_read = buf.len().min(self.buffer.len());
+        buf[..bytes_read].copy_from_slice(&self.buffer[..bytes_read]);
+        self.buffer.drain(..bytes_read);
+
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Protocol;
+
+    #[test]
+    fn machine_id_by_model() {
+        assert_eq!(machine_id(Model::Ti83).unwrap(), PC_TI83);
+        let err = machine_id(Model::Ti84Plus).unwrap_err();
+        assert_eq!(err.model, Model::Ti84Plus);
+        assert_eq!(err.protocol, Protocol::Dusb);
+    }
+
+    #[test]
+    fn variable_header() {
+        let data = [0x09, 0x00, 0x05, b'P', b'R', b'O', b'G', 0, 0, 0, 0];
+        let variable = decode_variable_header(&data).unwrap();
+
+        assert_eq!(variable.name, VariableName::from("PROG"));
+        assert_eq!(variable.size(), Some(9));
+        assert_eq!(variable.kind(), Some(0xf0070005));
+        assert!(decode_variable_header(&data[..10]).is_err());
+    }
+}
@@ -1,54 +1,277 @@
 #![allow(clippy::unusual_byte_groupings)]
 
 use std::{
-    fs,
-    io::{self, Read},
-    path::Path,
+    cmp::Reverse,
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    mem,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
-use dusb::{Mode, Variable, VariableAttribute, VariableAttributeKind, VariableKind};
+use dusb::{
+    decode_picture, sort_variables, ArchiveFull, Mode, StorageLocation, UnknownVariableKindError,
+    Variable, VariableAttribute, VariableAttributeKind, VariableKind, VariableName,
+    VerificationFailed,
+};
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, DynamicImage, Frame,
+};
 use packet::raw::{self, RawPacket, RawPacketKind};
 use rusb::{Device, DeviceHandle, GlobalContext};
 
 use crate::{
-    dusb::{Parameter, ParameterKind, Screenshot, VariableContents},
-    packet::vtl::{self, VirtualPacket, VirtualPacketKind},
+    backup::PartialBackup,
+    dusb::{
+        AppInfo, BatteryStatus, BootInfo, ClockSettings, DeviceInfo, ExecuteAction, Parameter,
+        ParameterKind, ParameterSet, Screenshot, ScreenshotUnsupported, VariableContents,
+    },
+    keys::{KeyCode, NoKeyForChar},
+    legacy::LegacyCalculator,
+    model::{Model, NoIdList, Protocol, UnsupportedProtocol},
+    packet::vtl::{self, DeviceError, VirtualPacket, VirtualPacketKind},
+    tifiles::{AppNotVariable, FlashFile, NotAnApp, TiFile, TiFileEntry, FLASH_APP},
+    transfer::{
+        find_interface, CalculatorLocked, CalculatorReset, CancelToken, ReadBufferTooSmall,
+        TransferError, TransferStats, Transport, UsbInterface, UsbTransport,
+    },
 };
 
+mod backup;
 mod dusb;
+mod keys;
+mod legacy;
+#[cfg(test)]
+mod mock;
+mod model;
 mod packet;
+pub mod tifiles;
+mod transfer;
 mod util;
 
 const TI_VENDOR: u16 = 0x0451;
-const TI84_PLUS_SILVER: u16 = 0xe008;
+/// The largest raw packet size that works on every calculator, even ones that allocate more.
+/// This is libticalcs' `DUSB_DATA_SIZE` (1023 bytes less the 5 byte raw header), which it uses
+/// for every model and hardware revision, so the hardware version isn't taken into account.
+const SAFE_PACKET_SIZE: u32 = 1018;
+/// What [`Calculator::tune_buffer_size`] asks for, more than any calculator allocates.
+const PROBE_PACKET_SIZE: u32 = 4096;
+/// The attributes needed to put a variable back exactly as it was.
+const BACKUP_ATTRIBUTES: &[VariableAttributeKind] = &[
+    VariableAttributeKind::Size,
+    VariableAttributeKind::Kind,
+    VariableAttributeKind::Version,
+    VariableAttributeKind::Archived,
+    VariableAttributeKind::Locked,
+];
+/// How long [`Calculator::wake`] waits for a sleeping calculator before trying again.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often a read checks whether it was cancelled, see [`Calculator::cancel_token`].
+pub const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// The kind of the ID list, which is requested like a variable but isn't one.
+const ID_LIST_KIND: u32 = 0xf00c0000;
 
 pub struct Calculator {
-    pub device: DeviceHandle<GlobalContext>,
+    pub device: Box<dyn Transport>,
+    pub model: Model,
     pub max_raw_packet_size: u32,
     pub timeout: Duration,
+    /// How long receiving a whole virtual packet may take, across all of its raw packets.
+    pub packet_timeout: Duration,
     buffer: Vec<u8>,
+    /// The USB interface used, which has the endpoints below.
+    interface: u8,
     read_endpoint: u8,
+    write_endpoint: u8,
+    /// The largest USB packet the read endpoint sends.
+    max_packet_size: u16,
+    /// How many bytes each bulk read asks for.
+    read_buffer_size: usize,
     pub debug_transfer: bool,
+    /// Where transfers are dumped when `debug_transfer` is set. Defaults to stderr.
+    pub debug_output: Box<dyn Write + Send>,
+    allow_large_buffers: bool,
+    /// How many raw packets can be sent before waiting for their acknowledgments.
+    ack_window: usize,
+    /// Totals for the transfers so far, if collecting them was enabled with
+    /// [`CalculatorBuilder::collect_stats`].
+    pub stats: Option<TransferStats>,
+    /// Set once [`Calculator::cancel_token`] has been called.
+    cancel: Option<CancelToken>,
 }
 
-impl Calculator {
-    pub fn new(device: DeviceHandle<GlobalContext>, timeout: Duration) -> anyhow::Result<Self> {
-        let mut calculator = Self {
-            device,
-            max_raw_packet_size: 1019,
-            timeout,
-            buffer: Vec::new(),
-            read_endpoint: 129,
+pub struct CalculatorBuilder {
+    model: Model,
+    timeout: Duration,
+    packet_timeout: Duration,
+    buffer_size: u32,
+    /// `None` for enough to hold a whole raw packet of `buffer_size`.
+    read_buffer_size: Option<usize>,
+    allow_large_buffers: bool,
+    debug_transfer: bool,
+    collect_stats: bool,
+    ack_window: usize,
+    interface: Option<u8>,
+}
+
+impl CalculatorBuilder {
+    pub fn new(model: Model) -> Self {
+        Self {
+            model,
+            timeout: Duration::from_secs(10),
+            packet_timeout: Duration::from_secs(60),
+            buffer_size: 1019,
+            read_buffer_size: None,
+            allow_large_buffers: false,
             debug_transfer: false,
+            collect_stats: false,
+            ack_window: 1,
+            interface: None,
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn packet_timeout(mut self, packet_timeout: Duration) -> Self {
+        self.packet_timeout = packet_timeout;
+        self
+    }
+
+    /// The raw packet size to ask for when connecting.
+    pub fn buffer_size(mut self, buffer_size: u32) -> Self {
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// How many bytes to ask for in each USB read. Reads are combined as needed, so this only
+    /// affects throughput. Defaults to a whole raw packet of [`Self::buffer_size`], header
+    /// included, and building fails with [`ReadBufferTooSmall`] if it's set any lower than
+    /// the buffer size.
+    pub fn read_buffer_size(mut self, read_buffer_size: usize) -> Self {
+        self.read_buffer_size = Some(read_buffer_size);
+        self
+    }
+
+    /// Keeps whatever buffer size the calculator responds with, instead of clamping it to 1018.
+    ///
+    /// **This is only meant for protocol research.** The 83PCE/84+CE report sizes they can't
+    /// actually handle, so transfers are likely to fail with this enabled.
+    pub fn allow_large_buffers(mut self, allow_large_buffers: bool) -> Self {
+        self.allow_large_buffers = allow_large_buffers;
+        self
+    }
+
+    pub fn debug_transfer(mut self, debug_transfer: bool) -> Self {
+        self.debug_transfer = debug_transfer;
+        self
+    }
+
+    /// Lets up to `ack_window` raw packets be sent before waiting for their acknowledgments,
+    /// instead of waiting after every one. Defaults to 1.
+    ///
+    /// **This is experimental.** It can speed up large transfers, but it's untested whether
+    /// every calculator tolerates packets arriving before it has acknowledged the previous one.
+    pub fn ack_window(mut self, ack_window: usize) -> Self {
+        self.ack_window = ack_window.max(1);
+        self
+    }
+
+    /// Keeps track of how much is transferred and how long it takes, see [`TransferStats`].
+    pub fn collect_stats(mut self, collect_stats: bool) -> Self {
+        self.collect_stats = collect_stats;
+        self
+    }
+
+    /// The USB interface to use. By default it's the first one with a pair of bulk
+    /// endpoints, which is interface 0 on every calculator but can differ behind some cables
+    /// and composite devices.
+    pub fn interface(mut self, interface: u8) -> Self {
+        self.interface = Some(interface);
+        self
+    }
+
+    /// Claims the calculator's interface and negotiates the packet size. Fails with
+    /// [`UnsupportedProtocol`] for models that don't use DUSB, see [`LegacyCalculator`] for
+    /// those with a link cable.
+    pub fn build(self, device: DeviceHandle<GlobalContext>) -> anyhow::Result<Calculator> {
+        let interface = find_interface(&device.device(), self.interface)?;
+        device.claim_interface(interface.number)?;
+
+        self.build_with_transport(Box::new(UsbTransport::new(device)), interface)
+    }
+
+    /// Like [`Self::build`], but talks to the calculator through `transport` on an interface
+    /// that's already been claimed.
+    pub fn build_with_transport(
+        self,
+        transport: Box<dyn Transport>,
+        interface: UsbInterface,
+    ) -> anyhow::Result<Calculator> {
+        if self.model.protocol() != Protocol::Dusb {
+            return Err(UnsupportedProtocol {
+                model: self.model,
+                protocol: self.model.protocol(),
+            }
+            .into());
+        }
+
+        let read_buffer_size = match self.read_buffer_size {
+            Some(size) if size < self.buffer_size as usize => {
+                return Err(ReadBufferTooSmall {
+                    read_buffer_size: size,
+                    buffer_size: self.buffer_size,
+                }
+                .into())
+            }
+            Some(size) => size,
+            None => self.buffer_size as usize + raw::RAW_HEADER_LENGTH,
         };
 
-        calculator.negotiate_packet_size(1019)?;
+        let mut calculator = Calculator {
+            device: transport,
+            model: self.model,
+            max_raw_packet_size: self.buffer_size,
+            timeout: self.timeout,
+            packet_timeout: self.packet_timeout,
+            buffer: Vec::new(),
+            interface: interface.number,
+            read_endpoint: interface.read_endpoint,
+            write_endpoint: interface.write_endpoint,
+            max_packet_size: interface.max_packet_size,
+            read_buffer_size,
+            debug_transfer: self.debug_transfer,
+            debug_output: Box::new(io::stderr()),
+            allow_large_buffers: self.allow_large_buffers,
+            ack_window: self.ack_window,
+            stats: self.collect_stats.then(TransferStats::default),
+            cancel: None,
+        };
+
+        calculator.negotiate_packet_size(self.buffer_size)?;
 
         Ok(calculator)
     }
+}
+
+impl Calculator {
+    pub fn new(
+        device: DeviceHandle<GlobalContext>,
+        model: Model,
+        timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        CalculatorBuilder::new(model).timeout(timeout).build(device)
+    }
+
+    pub fn builder(model: Model) -> CalculatorBuilder {
+        CalculatorBuilder::new(model)
+    }
 
     pub fn negotiate_packet_size(&mut self, max: u32) -> anyhow::Result<()> {
         RawPacket::RequestBufSize(max).send(self)?;
@@ -57,11 +280,15 @@ impl Calculator {
         match packet {
             RawPacket::RespondBufSize(mut size) => {
                 println!("TI->PC: Responded with buffer size {size}");
-                if size > 1018 {
-                    println!(
-                    "[The 83PCE/84+CE allocate more than they support. Clamping buffer size to 1018]"
-                );
-                    size = 1018;
+                if size > SAFE_PACKET_SIZE {
+                    if self.allow_large_buffers {
+                        println!("[Keeping buffer size {size}, transfers may fail]");
+                    } else {
+                        println!(
+                            "[The 83PCE/84+CE allocate more than they support. Clamping buffer size to 1018]"
+                        );
+                        size = SAFE_PACKET_SIZE;
+                    }
                 };
                 self.max_raw_packet_size = size;
                 Ok(())
@@ -74,48 +301,391 @@ impl Calculator {
         }
     }
 
+    /// Finds the largest raw packet size that actually works and switches to it. Sizes above
+    /// what every calculator supports are checked with a transfer that fills a whole packet in
+    /// each direction, since what the calculator allocates can't be trusted. Each failed check
+    /// waits out the timeout, so this can take a while.
+    ///
+    /// If a size above 1018 works, large buffers are allowed from then on so it's kept.
+    pub fn tune_buffer_size(&mut self) -> anyhow::Result<u32> {
+        let allow_large_buffers = mem::replace(&mut self.allow_large_buffers, true);
+        let working = self.find_working_buffer_size();
+        // Put the setting back however probing went, so a failure doesn't leave it changed
+        self.allow_large_buffers = allow_large_buffers
+            || working
+                .as_ref()
+                .is_ok_and(|working| *working > SAFE_PACKET_SIZE);
+        let working = working?;
+
+        self.negotiate_packet_size(working)?;
+        self.max_raw_packet_size = self.max_raw_packet_size.min(working);
+        println!("[Using buffer size {}]", self.max_raw_packet_size);
+
+        Ok(self.max_raw_packet_size)
+    }
+
+    /// The search behind [`Self::tune_buffer_size`], which has to be run with large buffers
+    /// allowed.
+    fn find_working_buffer_size(&mut self) -> anyhow::Result<u32> {
+        self.negotiate_packet_size(PROBE_PACKET_SIZE)?;
+        let allocated = self.max_raw_packet_size;
+        println!("[Calculator allocated {allocated} bytes, checking which sizes work]");
+
+        let mut working = allocated.min(SAFE_PACKET_SIZE);
+        let mut failing = allocated + 1;
+        while failing - working > 1 {
+            // Try the allocated size first, it's the most likely to work
+            let size = if failing > allocated {
+                allocated
+            } else {
+                working + (failing - working) / 2
+            };
+
+            if self.check_packet_size(size, working)? {
+                working = size;
+            } else {
+                failing = size;
+            }
+        }
+
+        Ok(working)
+    }
+
+    /// Tries a transfer with full packets of `size` bytes, going back to `fallback` if it fails.
+    fn check_packet_size(&mut self, size: u32, fallback: u32) -> anyhow::Result<bool> {
+        self.negotiate_packet_size(size)?;
+        self.max_raw_packet_size = self.max_raw_packet_size.min(size);
+
+        // The request's raw packet is the virtual packet's size, kind and count, then two bytes
+        // per parameter. The response is larger than the request.
+        let count = (size as usize).saturating_sub(8) / 2;
+        match self.request_parameters(&vec![ParameterKind::ScreenWidth; count]) {
+            Ok(_) => Ok(true),
+            Err(err) => {
+                println!("[Buffer size {size} failed: {err}]");
+                self.max_raw_packet_size = fallback;
+                self.recover()?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Checks that the calculator is still responding by renegotiating the packet size, which
+    /// is the smallest exchange there is. Fails with the read error (normally a timeout) if the
+    /// calculator doesn't answer within [`Self::timeout`].
+    pub fn ping(&mut self) -> anyhow::Result<()> {
+        self.negotiate_packet_size(self.max_raw_packet_size)
+    }
+
+    /// Checks whether the calculator is still plugged in, without sending it anything. Unlike
+    /// [`Self::ping`], this can't tell whether it's actually responding.
+    pub fn is_connected(&self) -> bool {
+        self.device.is_connected()
+    }
+
+    /// Finds the calculator again after it was unplugged or reset, and sets up a new session
+    /// with it. Only the same calculator is used (see
+    /// [`DeviceIdentity`](transfer::DeviceIdentity)), even if others are plugged in, and it
+    /// fails with [`CalculatorReset`] if it can't be found.
+    pub fn reconnect(&mut self) -> anyhow::Result<()> {
+        println!("[Reconnecting to {:?}]", self.model);
+        self.device.reconnect(self.model, self.interface)?;
+        self.buffer.clear();
+        self.negotiate_packet_size(self.max_raw_packet_size)?;
+        self.ready_check()
+    }
+
+    /// Runs `operation`, and if the calculator disconnects during it, reconnects and tries once
+    /// more. The operation has to be safe to repeat from the start.
+    pub fn with_reconnect<T, F>(&mut self, mut operation: F) -> anyhow::Result<T>
+    where
+        F: FnMut(&mut Self) -> anyhow::Result<T>,
+    {
+        match operation(self) {
+            Err(err) if TransferError::is_disconnect(&err) => {
+                self.reconnect().map_err(|_| err.context(CalculatorReset))?;
+                operation(self)
+            }
+            result => result,
+        }
+    }
+
+    /// Throws away anything left over from an earlier exchange, both in the read buffer and still
+    /// waiting on the USB endpoint. Useful after an error left a transfer half-read.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.buffer.clear();
+
+        let mut chunk = vec![0; self.read_buffer_size];
+        loop {
+            match self
+                .device
+                .read_bulk(self.read_endpoint, &mut chunk, Duration::from_millis(50))
+            {
+                Ok(0) | Err(rusb::Error::Timeout) => return Ok(()),
+                Ok(bytes) => println!("[Discarded {bytes} stale bytes]"),
+                Err(err) => return Err(TransferError::from(err).into()),
+            }
+        }
+    }
+
+    /// Gets the connection back in sync after a failed operation by throwing away anything left
+    /// over and renegotiating the packet size. There's no status request in the protocol, and
+    /// the calculator doesn't hold on to errors between operations, so there's nothing to clear
+    /// on its side.
+    pub fn recover(&mut self) -> anyhow::Result<()> {
+        self.flush()?;
+        self.ping()
+    }
+
     pub fn request_parameters(
         &mut self,
         parameters: &[ParameterKind],
-    ) -> anyhow::Result<Vec<Parameter>> {
+    ) -> anyhow::Result<ParameterSet> {
         self.negotiate_packet_size(self.max_raw_packet_size)?;
 
         println!("PC->TI: Requesting parameters {parameters:?}");
 
         VirtualPacket::ParameterRequest(parameters.to_vec()).send(self)?;
 
-        Ok(match VirtualPacket::receive(self)? {
-            VirtualPacket::ParameterResponse(parameters) => parameters,
-            packet => {
-                return Err(vtl::WrongPacketKind {
-                    expected: VirtualPacketKind::ParameterResponse,
-                    received: packet.into(),
+        Ok(
+            match VirtualPacket::receive_during(self, "requesting parameters")? {
+                VirtualPacket::ParameterResponse(parameters) => parameters,
+                packet => {
+                    return Err(vtl::WrongPacketKind {
+                        expected: VirtualPacketKind::ParameterResponse,
+                        received: packet.into(),
+                    }
+                    .into())
                 }
-                .into())
+            },
+        )
+    }
+
+    /// Gets the product name, or `None` if the calculator doesn't report one. It can't be
+    /// changed, so there's no matching `set_name`.
+    pub fn get_name(&mut self) -> anyhow::Result<Option<String>> {
+        let parameters = self.request_parameters(&[ParameterKind::Name])?;
+        Ok(parameters.name().map(str::to_owned))
+    }
+
+    /// Gets the product name, hardware revision and OS version, each `None` if the calculator
+    /// doesn't report it.
+    pub fn device_info(&mut self) -> anyhow::Result<DeviceInfo> {
+        let parameters = self.request_parameters(&[
+            ParameterKind::Name,
+            ParameterKind::HardwareVersion,
+            ParameterKind::OsVersion,
+        ])?;
+
+        Ok(DeviceInfo {
+            name: parameters.name().map(str::to_owned),
+            hardware_version: parameters.hardware_version(),
+            os_version: parameters.os_version(),
+        })
+    }
+
+    /// Changes a parameter, like the clock settings. Most parameters can't be changed, which the
+    /// calculator reports as [`DeviceError::InvalidParameter`].
+    pub fn set_parameter(&mut self, parameter: Parameter) -> anyhow::Result<()> {
+        println!("PC->TI: Setting parameter {parameter:?}");
+
+        VirtualPacket::ParameterSet(parameter).send(self)?;
+        VirtualPacket::receive_acknowledge(self, VirtualPacketKind::DataAcknowledge)
+    }
+
+    pub fn clock_settings(&mut self) -> anyhow::Result<ClockSettings> {
+        let parameters = self.request_parameters(&[
+            ParameterKind::ClockOn,
+            ParameterKind::Clock,
+            ParameterKind::DateFormat,
+            ParameterKind::Clock24Hour,
+        ])?;
+
+        Ok(ClockSettings {
+            enabled: parameters
+                .clock_on()
+                .context("missing clock on parameter")?,
+            time: parameters.clock().context("missing clock parameter")?,
+            date_format: parameters
+                .date_format()
+                .context("missing date format parameter")?,
+            twenty_four_hour: parameters
+                .clock_24_hour()
+                .context("missing 24 hour clock parameter")?,
+        })
+    }
+
+    pub fn set_clock_settings(&mut self, settings: ClockSettings) -> anyhow::Result<()> {
+        self.set_parameter(Parameter::ClockOn(settings.enabled))?;
+        self.set_parameter(Parameter::Clock(settings.time))?;
+        self.set_parameter(Parameter::DateFormat(settings.date_format))?;
+        self.set_parameter(Parameter::Clock24Hour(settings.twenty_four_hour))
+    }
+
+    /// Gets the screen's width, height and contents, without converting the contents to an
+    /// image. Monochrome screens are recognized by the model (see [`Screenshot::with_model`]).
+    pub fn screenshot_raw(&mut self) -> anyhow::Result<(u32, u32, Screenshot)> {
+        let parameters = self.request_parameters(&[
+            ParameterKind::ScreenWidth,
+            ParameterKind::ScreenHeight,
+            ParameterKind::ScreenContents,
+        ])?;
+
+        let (default_width, default_height, _) = self.model.screen_dimensions();
+        let width = parameters
+            .screen_width()
+            .map_or(default_width, |width| width as u32);
+        let height = parameters
+            .screen_height()
+            .map_or(default_height, |height| height as u32);
+        // Models that can't send their screen mark the parameter as invalid, so it never shows up
+        let screenshot = parameters
+            .parameters
+            .into_iter()
+            .find_map(|parameter| match parameter {
+                Parameter::ScreenContents(screenshot) => Some(screenshot),
+                _ => None,
+            })
+            .ok_or(ScreenshotUnsupported)?;
+
+        Ok((width, height, screenshot.with_model(self.model)))
+    }
+
+    /// Records the screen into an animated GIF, taking a screenshot every `delay_ms`. If a
+    /// screenshot takes longer than that, the next one is taken straight away and the frame is
+    /// shown for as long as it actually took.
+    pub fn record_gif(&mut self, frames: usize, delay_ms: u16, path: &Path) -> anyhow::Result<()> {
+        let interval = Duration::from_millis(delay_ms as u64);
+        let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+        encoder.set_repeat(Repeat::Infinite)?;
+
+        let mut previous: Option<(Instant, image::RgbImage)> = None;
+        for _ in 0..frames {
+            if let Some((taken, _)) = &previous {
+                thread::sleep(interval.saturating_sub(taken.elapsed()));
+            }
+
+            let taken = Instant::now();
+            let (width, height, screenshot) = self.screenshot_raw()?;
+            let image = screenshot.to_image(width, height)?;
+
+            // A frame's delay isn't known until the next one is taken
+            if let Some((previous_taken, previous_image)) = previous.replace((taken, image)) {
+                let delay = Delay::from_saturating_duration(taken - previous_taken);
+                encoder.encode_frame(gif_frame(previous_image, delay))?;
             }
+        }
+        if let Some((_, image)) = previous {
+            let delay = Delay::from_saturating_duration(interval);
+            encoder.encode_frame(gif_frame(image, delay))?;
+        }
+
+        Ok(())
+    }
+
+    /// Free Flash space in bytes, worked out from the free app pages with
+    /// [`Model::app_pages_to_bytes`]. `None` if the calculator doesn't report it.
+    pub fn free_bytes(&mut self) -> anyhow::Result<Option<u64>> {
+        if self.model.app_page_size().is_none() {
+            return Ok(None);
+        }
+        let parameters = self.request_parameters(&[ParameterKind::FreeAppPages])?;
+        Ok(parameters
+            .free_app_pages()
+            .and_then(|pages| self.model.app_pages_to_bytes(pages)))
+    }
+
+    /// Used Flash space in bytes, worked out the same way as [`Self::free_bytes`].
+    pub fn used_bytes(&mut self) -> anyhow::Result<Option<u64>> {
+        if self.model.app_page_size().is_none() {
+            return Ok(None);
+        }
+        let parameters =
+            self.request_parameters(&[ParameterKind::TotalAppPages, ParameterKind::FreeAppPages])?;
+        Ok(parameters
+            .total_app_pages()
+            .zip(parameters.free_app_pages())
+            .and_then(|(total, free)| self.model.app_pages_to_bytes(total.saturating_sub(free))))
+    }
+
+    /// The boot code and OS versions and which of them is running, to check whether the
+    /// calculator is ready to receive an OS.
+    pub fn boot_info(&mut self) -> anyhow::Result<BootInfo> {
+        let parameters = self.request_parameters(&[
+            ParameterKind::BootVersion,
+            ParameterKind::OsMode,
+            ParameterKind::OsVersion,
+        ])?;
+
+        Ok(BootInfo {
+            boot_version: parameters
+                .boot_version()
+                .context("missing boot version parameter")?,
+            os_version: parameters.os_version(),
+            mode: parameters.os_mode().context("missing OS mode parameter")?,
         })
     }
 
+    /// Checks the batteries, or `None` if the calculator doesn't report them.
+    pub fn battery(&mut self) -> anyhow::Result<Option<BatteryStatus>> {
+        let parameters = self.request_parameters(&[ParameterKind::Battery])?;
+        Ok(parameters.battery())
+    }
+
     pub fn request_directory(
         &mut self,
         attributes: &[VariableAttributeKind],
     ) -> anyhow::Result<Vec<Variable>> {
+        let mut variables = Vec::new();
+        self.request_directory_with(attributes, |variable| {
+            variables.push(variable);
+            ControlFlow::Continue(())
+        })?;
+
+        Ok(variables)
+    }
+
+    /// Counts the variables on the calculator. There's no way to ask for just the count, so this
+    /// still goes through the whole listing, only without keeping any of it.
+    pub fn variable_count(&mut self) -> anyhow::Result<usize> {
+        let mut count = 0;
+        self.request_directory_with(&[], |_| {
+            count += 1;
+            ControlFlow::Continue(())
+        })?;
+
+        Ok(count)
+    }
+
+    /// Lists the variables on the calculator, passing each one to `callback` as it arrives.
+    /// Once the callback returns [`ControlFlow::Break`] it isn't called again, but the rest of
+    /// the listing is still received so the connection stays in sync.
+    pub fn request_directory_with<F>(
+        &mut self,
+        attributes: &[VariableAttributeKind],
+        mut callback: F,
+    ) -> anyhow::Result<()>
+    where
+        F: FnMut(Variable) -> ControlFlow<()>,
+    {
         VirtualPacket::DirectoryRequest(attributes.to_vec()).send(self)?;
 
-        let mut variables = Vec::new();
+        let mut stopped = false;
         loop {
-            let mut packet = VirtualPacket::receive(self)?;
+            let mut packet = VirtualPacket::receive_during(self, "listing variables")?;
             if let VirtualPacket::Wait(ms) = packet {
                 println!("Waiting {ms}ms...");
                 thread::sleep(Duration::from_millis(100));
-                packet = VirtualPacket::receive(self)?;
+                packet = VirtualPacket::receive_during(self, "listing variables")?;
             }
 
             match packet {
                 VirtualPacket::VariableHeader(variable) => {
-                    variables.push(variable);
+                    if !stopped {
+                        stopped = callback(variable).is_break();
+                    }
                 }
-                VirtualPacket::EndOfTransmission => return Ok(variables),
+                VirtualPacket::EndOfTransmission => return Ok(()),
                 packet => {
                     return Err(vtl::WrongPacketKind {
                         expected: VirtualPacketKind::VariableHeader,
@@ -127,225 +697,851 @@ impl Calculator {
         }
     }
 
-    pub fn request_variable(&mut self, name: String) -> anyhow::Result<VariableContents> {
+    /// Lists the installed Flash apps. There's no separate request for apps, they're listed
+    /// along with the variables, so this goes through the whole directory.
+    pub fn list_apps(&mut self) -> anyhow::Result<Vec<AppInfo>> {
+        let page_size = self.model.app_page_size();
+        let mut apps = Vec::new();
+        self.request_directory_with(
+            &[VariableAttributeKind::Size, VariableAttributeKind::Kind],
+            |variable| {
+                if variable.kind() == Some(VariableKind::App as u32) {
+                    let size = variable.size().unwrap_or_default();
+                    apps.push(AppInfo {
+                        name: variable.name,
+                        size,
+                        pages: page_size.map(|page_size| (size as u64).div_ceil(page_size) as u32),
+                    });
+                }
+                ControlFlow::Continue(())
+            },
+        )?;
+
+        Ok(apps)
+    }
+
+    /// Checks whether there's a variable with this name and kind.
+    pub fn variable_exists(
+        &mut self,
+        name: &VariableName,
+        kind: VariableKind,
+    ) -> anyhow::Result<bool> {
+        self.variable_id_exists(name, kind as u32)
+    }
+
+    /// Like [`Self::variable_exists`], but with the kind's ID so it works for kinds that
+    /// aren't known too.
+    fn variable_id_exists(&mut self, name: &VariableName, kind: u32) -> anyhow::Result<bool> {
+        let mut exists = false;
+        self.request_directory_with(&[VariableAttributeKind::Kind], |variable| {
+            exists = variable.name == *name && variable.kind() == Some(kind);
+            if exists {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })?;
+
+        Ok(exists)
+    }
+
+    /// Lists the archived variables with their kind and size, largest first, to see what's
+    /// taking up archive space.
+    pub fn list_archived(&mut self) -> anyhow::Result<Vec<Variable>> {
+        let variables = self.request_directory(&[
+            VariableAttributeKind::Size,
+            VariableAttributeKind::Kind,
+            VariableAttributeKind::Archived,
+        ])?;
+
+        Ok(archived_by_size(variables))
+    }
+
+    /// Lists the variables with every attribute the calculator has for each of them, see
+    /// [`VariableAttributeKind::ALL`].
+    pub fn full_directory(&mut self) -> anyhow::Result<Vec<Variable>> {
+        self.request_directory(VariableAttributeKind::ALL)
+    }
+
+    /// Like [`Self::request_directory`], but always includes the `Kind` and `Archived`
+    /// attributes and returns the variables in a stable order (see [`sort_variables`]).
+    pub fn request_directory_sorted(
+        &mut self,
+        attributes: &[VariableAttributeKind],
+    ) -> anyhow::Result<Vec<Variable>> {
+        let mut attributes = attributes.to_vec();
+        for required in [VariableAttributeKind::Kind, VariableAttributeKind::Archived] {
+            if !attributes.contains(&required) {
+                attributes.push(required);
+            }
+        }
+
+        let mut variables = self.request_directory(&attributes)?;
+        sort_variables(&mut variables);
+        Ok(variables)
+    }
+
+    /// Requests a variable and receives its header. The kind is sent along if it's known, since
+    /// the same name can be used by variables of different kinds.
+    fn request_variable_header(
+        &mut self,
+        name: VariableName,
+        kind: Option<u32>,
+        attributes: &[VariableAttributeKind],
+    ) -> anyhow::Result<Variable> {
+        let operation = format!("requesting {name}");
         let packet = VirtualPacket::RequestVariable(
             name,
-            vec![
-                VariableAttributeKind::Archived,
-                VariableAttributeKind::Version,
-                VariableAttributeKind::Size,
-                VariableAttributeKind::Kind,
-            ],
-            vec![VariableAttribute::Kind2(0xf00e001a)],
+            attributes.to_vec(),
+            kind.map(|kind| vec![VariableAttribute::Kind2(kind)])
+                .unwrap_or_default(),
         );
         packet.send(self)?;
 
-        let kind = match VirtualPacket::receive(self)? {
-            VirtualPacket::VariableHeader(variable) => VariableKind::from_repr(
-                variable
-                    .attributes
-                    .iter()
-                    .find_map(|attr| {
-                        if let VariableAttribute::Kind(kind) = attr {
-                            Some(*kind)
-                        } else {
-                            None
-                        }
-                    })
-                    .unwrap(),
-            )
-            .unwrap(),
-
-            VirtualPacket::Error(err) => return Err(err.into()),
+        match VirtualPacket::receive_during(self, &operation)? {
+            VirtualPacket::VariableHeader(variable) => Ok(variable),
             packet => {
-                return Err(
-                    vtl::WrongPacketKind::new(VirtualPacketKind::VariableHeader, packet).into(),
-                );
+                Err(vtl::WrongPacketKind::new(VirtualPacketKind::VariableHeader, packet).into())
             }
-        };
+        }
+    }
 
-        match VirtualPacket::receive(self)? {
-            VirtualPacket::VariableContents(contents) => {
-                Ok(VariableContents::from_payload(kind, &contents)?)
-            }
+    fn receive_variable_contents(&mut self) -> anyhow::Result<Vec<u8>> {
+        match VirtualPacket::receive_during(self, "receiving variable contents")? {
+            VirtualPacket::VariableContents(contents) => Ok(contents),
             packet => {
                 Err(vtl::WrongPacketKind::new(VirtualPacketKind::VariableContents, packet).into())
             }
         }
     }
 
-    pub fn send_variable(
+    pub fn request_variable(
         &mut self,
-        header: Variable,
-        contents: VariableContents,
-    ) -> anyhow::Result<()> {
-        VirtualPacket::RequestToSend(header).send(self)?;
-        VirtualPacket::VariableContents(contents.into_payload()).send(self)?;
-        match VirtualPacket::receive(self)? {
-            VirtualPacket::DataAcknowledge => {}
-            packet => {
-                return Err(
-                    vtl::WrongPacketKind::new(VirtualPacketKind::DataAcknowledge, packet).into(),
-                );
-            }
-        }
-        VirtualPacket::EndOfTransmission.send(self)?;
+        name: impl Into<VariableName>,
+        kind: VariableKind,
+    ) -> anyhow::Result<VariableContents> {
+        self.request_variable_header(
+            name.into(),
+            Some(kind as u32),
+            &[
+                VariableAttributeKind::Archived,
+                VariableAttributeKind::Version,
+                VariableAttributeKind::Size,
+                VariableAttributeKind::Kind,
+            ],
+        )?;
 
-        Ok(())
+        let contents = self.receive_variable_contents()?;
+        VariableContents::from_payload(kind as u32, &contents)
     }
 
-    pub fn set_mode(&mut self, mode: Mode) -> anyhow::Result<()> {
-        self.negotiate_packet_size(self.max_raw_packet_size)?;
+    /// Reads `len` bytes of a variable's data starting at `offset`, or fewer if the variable
+    /// ends first. DUSB can only send whole variables, so this still transfers all of it.
+    pub fn read_variable_range(
+        &mut self,
+        name: impl Into<VariableName>,
+        kind: VariableKind,
+        offset: usize,
+        len: usize,
+    ) -> anyhow::Result<Vec<u8>> {
+        let data = self.request_variable(name, kind)?.into_data();
+        let start = offset.min(data.len());
+        let end = offset.saturating_add(len).min(data.len());
 
-        VirtualPacket::SetMode(mode).send(self)?;
-        match VirtualPacket::receive(self)? {
-            VirtualPacket::SetModeAcknowledge => Ok(()),
-            packet => Err(vtl::WrongPacketKind {
-                expected: VirtualPacketKind::SetModeAcknowledge,
-                received: packet.into(),
-            }
-            .into()),
-        }
+        Ok(data[start..end].to_vec())
     }
 
-    pub fn send(&self, bytes: &[u8]) -> anyhow::Result<()> {
-        if self.debug_transfer {
-            println!("Sending {} bytes...", bytes.len());
-            println!("{bytes:02x?}");
+    /// Gets the calculator's ID list, the unique ID it reports for licensing, as a hex string.
+    /// Fails with [`NoIdList`] on models that don't have one.
+    pub fn get_id_list(&mut self) -> anyhow::Result<String> {
+        if !self.model.has_id_list() {
+            return Err(NoIdList(self.model).into());
         }
 
-        self.device.write_bulk(2, bytes, Duration::from_secs(5))?;
-        Ok(())
+        self.request_variable_header(
+            VariableName::from("IDList"),
+            Some(ID_LIST_KIND),
+            &[
+                VariableAttributeKind::Archived,
+                VariableAttributeKind::Version,
+            ],
+        )?;
+        let contents = self.receive_variable_contents()?;
+
+        Ok(decode_id_list(&contents)?)
     }
-}
 
-impl Read for Calculator {
-    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if self.debug_transfer {
-            println!("Receiving {} bytes...", buf.len());
-        }
+    /// Pulls a variable along with everything needed to put it back as it was, including
+    /// whether it's archived or locked. The header can be passed straight to
+    /// [`Self::send_variable`] to restore it.
+    pub fn backup_variable(
+        &mut self,
+        name: impl Into<VariableName>,
+    ) -> anyhow::Result<(Variable, VariableContents)> {
+        let header = self.request_variable_header(name.into(), None, BACKUP_ATTRIBUTES)?;
+        let contents = self.receive_variable_contents()?;
 
-        if buf.len() > self.buffer.len() && !self.buffer.is_empty() {
-            let bytes_read = self.buffer.len();
-            buf[0..bytes_read].copy_from_slice(&self.buffer);
-            self.buffer.clear();
+        let contents =
+            VariableContents::from_payload(header.kind().unwrap_or_default(), &contents)?;
 
-            return Ok(bytes_read);
-        }
+        Ok((header, contents))
+    }
 
-        if buf.len() > self.max_raw_packet_size as usize {
-            return self.read(&mut buf[0..self.max_raw_packet_size as usize]);
-        }
+    /// Requests several variables over the same connection, returning a result for each one in
+    /// order. A failed request doesn't stop the rest of the batch, the connection is recovered
+    /// (see [`Self::recover`]) before moving on.
+    pub fn request_variables(
+        &mut self,
+        names: &[(VariableName, VariableKind)],
+    ) -> Vec<anyhow::Result<VariableContents>> {
+        names
+            .iter()
+            .map(|(name, kind)| {
+                let result = self.request_variable(name, *kind);
+                if result.is_err() {
+                    if let Err(err) = self.recover() {
+                        println!("[Couldn't recover after failed request for {name}: {err}]");
+                    }
+                }
+                result
+            })
+            .collect()
+    }
 
-        if self.buffer.is_empty() {
-            self.buffer.resize(1024, 0);
-            let bytes_read =
-                match self
-                    .device
-                    .read_bulk(self.read_endpoint, &mut self.buffer, self.timeout)
-                {
-                    Ok(bytes) => bytes,
-                    Err(err) => return Err(io::Error::new(io::ErrorKind::Other, err)),
-                };
-            self.buffer.truncate(bytes_read);
-        }
+    /// Requests only the given attributes of a variable, without decoding its contents.
+    pub fn variable_attributes(
+        &mut self,
+        name: impl Into<VariableName>,
+        attributes: &[VariableAttributeKind],
+    ) -> anyhow::Result<Vec<VariableAttribute>> {
+        let header = self.request_variable_header(name.into(), None, attributes)?;
+
+        // The calculator always follows the header with the contents and there's no way to
+        // decline them, so they still have to be received to keep the connection in sync
+        self.receive_variable_contents()?;
 
-        let bytes_requested = buf.len();
-        let (requested, leftover) = self.buffer.split_at(bytes_requested);
+        Ok(header.attributes)
+    }
 
-        buf.copy_from_slice(requested);
-        self.buffer = leftover.to_owned();
+    /// Saves a variable as a TI file at `path`, with the extension replaced to match the
+    /// variable's kind. Returns the path that was actually written.
+    ///
+    /// The contents are written out exactly as received instead of being decoded first, so
+    /// large variables aren't held in memory twice.
+    ///
+    /// Apps are Flash files, not variable files, so pulling one fails with [`AppNotVariable`].
+    pub fn pull_variable_to_file(
+        &mut self,
+        name: impl Into<VariableName>,
+        path: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        let (kind, entry) = self.pull_file_entry(name.into(), None)?;
 
-        if self.debug_transfer {
-            println!("{buf:02x?}");
-        }
+        let path = path.with_extension(kind.file_extension());
+        TiFile::new(vec![entry]).write(BufWriter::new(File::create(&path)?))?;
 
-        Ok(bytes_requested)
+        Ok(path)
     }
-}
-
-fn find_calculator() -> anyhow::Result<Option<Device<GlobalContext>>> {
-    Ok(rusb::devices()?.iter().find(|device| {
-        let descriptor = device.device_descriptor().unwrap();
-        descriptor.vendor_id() == TI_VENDOR && descriptor.product_id() == TI84_PLUS_SILVER
-    }))
-}
 
-fn _take_screenshot<P>(calculator: &mut Calculator, output_path: P) -> anyhow::Result<()>
-where
-    P: AsRef<Path>,
-{
-    let parameters = calculator.request_parameters(&[
-        ParameterKind::ScreenWidth,
-        ParameterKind::ScreenHeight,
-        ParameterKind::ScreenContents,
-    ])?;
+    /// Pulls a variable as an entry for a TI file, along with its kind. The kind should be
+    /// given if it's known, see [`Self::request_variable_header`].
+    fn pull_file_entry(
+        &mut self,
+        name: VariableName,
+        kind: Option<u32>,
+    ) -> anyhow::Result<(VariableKind, TiFileEntry)> {
+        let header = self.request_variable_header(
+            name,
+            kind,
+            &[
+                VariableAttributeKind::Archived,
+                VariableAttributeKind::Version,
+                VariableAttributeKind::Size,
+                VariableAttributeKind::Kind,
+            ],
+        )?;
+        let contents = self.receive_variable_contents()?;
 
-    let (mut width, mut height) = (0, 0);
-    let mut pixels = Vec::new();
-    for parameter in parameters {
-        match parameter {
-            Parameter::ScreenWidth(w) => width = w as u32,
-            Parameter::ScreenHeight(h) => height = h as u32,
-            Parameter::ScreenContents(Screenshot::Rgb(p)) => pixels = p.to_vec(),
-            _ => {}
+        let kind = header.kind().unwrap_or_default();
+        let kind = VariableKind::from_id(kind).ok_or(UnknownVariableKindError(kind))?;
+        // Apps are saved as Flash files, which have a different format altogether
+        if kind == VariableKind::App {
+            return Err(AppNotVariable.into());
         }
+
+        let entry = TiFileEntry {
+            name: header.name.clone(),
+            type_id: kind.file_type_byte(),
+            version: header.version().unwrap_or_default(),
+            archived: header.location() == Some(StorageLocation::Archive),
+            data: contents,
+        };
+
+        Ok((kind, entry))
     }
 
-    let mut img = image::RgbImage::new(width, height);
-    for y in 0..height {
-        for x in 0..width {
-            let pixel = pixels[(y * width + x) as usize];
-            let r = (pixel & 0b11111_000000_00000) >> 11;
-            let r = r as f32 / 31.0 * 255.0;
+    /// Backs up every variable except apps into a group file at `path`, which can be resumed if
+    /// it's interrupted. Until the backup finishes, each variable is saved to a partial backup
+    /// next to the group file as soon as it's pulled, with a manifest listing what's been saved
+    /// (see [`PartialBackup`]), and variables already in it are skipped on the next run. The
+    /// group file is only written once every variable has been pulled, gzip compressed if
+    /// `path` ends in `.gz`.
+    pub fn backup(&mut self, path: &Path) -> anyhow::Result<TiFile> {
+        let mut partial = PartialBackup::open(&PartialBackup::path_for(path))?;
+
+        let variables = self.request_directory(&[VariableAttributeKind::Kind])?;
+        for variable in variables {
+            let Some(kind) = variable.kind() else {
+                continue;
+            };
+            // Apps are Flash files, they can't go in a group
+            if kind == VariableKind::App as u32
+                || VariableKind::from_id(kind)
+                    .is_some_and(|kind| partial.contains(&variable.name, kind))
+            {
+                continue;
+            }
 
-            let g = (pixel & 0b00000_111111_00000) >> 5;
-            let g = g as f32 / 63.0 * 255.0;
+            let (_, entry) = self.pull_file_entry(variable.name, Some(kind))?;
+            partial.record(entry)?;
+        }
 
-            let b = pixel & 0b00000_000000_11111;
-            let b = b as f32 / 31.0 * 255.0;
+        partial.finish(path)
+    }
 
-            img.put_pixel(x, y, image::Rgb([r as u8, g as u8, b as u8]));
+    /// Saves a picture as a `.png` if that's the extension of `path`, otherwise as a TI file
+    /// like [`Self::pull_variable_to_file`]. Returns the path that was actually written.
+    pub fn pull_picture(
+        &mut self,
+        name: impl Into<VariableName>,
+        path: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        if path.extension() != Some("png".as_ref()) {
+            return self.pull_variable_to_file(name, path);
         }
+
+        let VariableContents::Picture(data) = self.request_variable(name, VariableKind::Picture)?
+        else {
+            unreachable!("requested a picture");
+        };
+        decode_picture(&data)?.save(path)?;
+
+        Ok(path.to_owned())
     }
-    img.save(output_path)?;
 
-    Ok(())
-}
+    pub fn supported_kinds(&self) -> &'static [VariableKind] {
+        self.model.supported_kinds()
+    }
+
+    /// Sends a variable to the calculator. It's written straight to the archive if the header
+    /// has `Archived(true)`, in which case running out of memory is reported as [`ArchiveFull`].
+    ///
+    /// If the calculator refuses to write a variable that doesn't exist yet, it can't be
+    /// because that variable is locked or archived, so it's reported as [`CalculatorLocked`].
+    ///
+    /// Kinds that aren't known are sent as is, the calculator knows best whether it can store
+    /// them.
+    pub fn send_variable(
+        &mut self,
+        header: Variable,
+        contents: VariableContents,
+    ) -> anyhow::Result<()> {
+        let kind = contents.kind_id();
+        self.send_variable_payload(header, kind, contents.into_payload())
+    }
+
+    /// Sends a variable's payload as is, for forwarding bytes that came from another
+    /// calculator or a file without decoding them first.
+    fn send_variable_payload(
+        &mut self,
+        header: Variable,
+        kind: u32,
+        payload: Vec<u8>,
+    ) -> anyhow::Result<()> {
+        if let Some(kind) = VariableKind::from_id(kind) {
+            self.model.check_supported(kind)?;
+        }
+
+        let name = header.name.clone();
+        match self.send_variable_packets(header, payload) {
+            Err(err) if matches!(err.downcast_ref(), Some(DeviceError::VariableUnwritable)) => {
+                self.recover()?;
+                if self.variable_id_exists(&name, kind)? {
+                    Err(err)
+                } else {
+                    Err(CalculatorLocked.into())
+                }
+            }
+            result => result,
+        }
+    }
+
+    fn send_variable_packets(&mut self, header: Variable, payload: Vec<u8>) -> anyhow::Result<()> {
+        let archived = header.location() == Some(StorageLocation::Archive);
+
+        let receive_acknowledge = |calculator: &mut Self| {
+            VirtualPacket::receive_acknowledge(calculator, VirtualPacketKind::DataAcknowledge)
+                .map_err(|err| match err.downcast_ref() {
+                    Some(DeviceError::OutOfMemory) if archived => ArchiveFull.into(),
+                    _ => err,
+                })
+        };
+
+        // The calculator can refuse the variable (not enough memory, locked, ...) as soon as it
+        // sees the header, before any contents are sent
+        VirtualPacket::RequestToSend(header).send(self)?;
+        receive_acknowledge(self)?;
+        VirtualPacket::VariableContents(payload).send(self)?;
+        receive_acknowledge(self)?;
+        VirtualPacket::EndOfTransmission.send(self)?;
+
+        Ok(())
+    }
+
+    /// Sends a variable like [`Self::send_variable`], then reads it back to check it was
+    /// stored exactly as sent, failing with [`VerificationFailed`] if it wasn't. Variables are
+    /// stored as the bytes they're sent as (programs are already tokenized), so the contents
+    /// are compared as they're sent over the wire. Whether it's archived or locked is checked
+    /// too, failing with [`AttributeNotApplied`](dusb::AttributeNotApplied) if the calculator
+    /// didn't apply it.
+    pub fn send_variable_verified(
+        &mut self,
+        header: Variable,
+        contents: VariableContents,
+    ) -> anyhow::Result<()> {
+        let sent_header = header.clone();
+        let kind = contents.kind_id();
+        let sent = contents.clone().into_payload();
+        self.send_variable(header, contents)?;
+
+        let stored_header = self.request_variable_header(
+            sent_header.name.clone(),
+            Some(kind),
+            &[
+                VariableAttributeKind::Archived,
+                VariableAttributeKind::Locked,
+            ],
+        )?;
+        let received = VariableContents::from_payload(kind, &self.receive_variable_contents()?)?
+            .into_payload();
+        if received != sent {
+            return Err(VerificationFailed {
+                name: sent_header.name,
+                sent: sent.len(),
+                received: received.len(),
+            }
+            .into());
+        }
+
+        Ok(sent_header.check_applied(&stored_header)?)
+    }
+
+    /// Sends data as a new variable of the given kind, in RAM and unlocked. The data is what
+    /// [`VariableContents`] holds, without any length prefix. The version defaults to 0, but
+    /// can be bumped when re-sending an edited variable.
+    pub fn send_raw_variable(
+        &mut self,
+        name: impl Into<VariableName>,
+        kind: VariableKind,
+        data: &[u8],
+        version: Option<u8>,
+    ) -> anyhow::Result<()> {
+        let contents = VariableContents::from_data(kind, data)?;
+        let header = raw_variable_header(name.into(), &contents, version.unwrap_or_default());
+
+        self.send_variable(header, contents)
+    }
+
+    /// Sends every variable in a group file, calling `progress` with each member's index, name,
+    /// bytes sent and total size before and after sending it. A member that fails is skipped
+    /// unless `stop_on_error` is set, and the errors are returned with the members' names.
+    pub fn send_group_with_progress<F>(
+        &mut self,
+        group: &TiFile,
+        stop_on_error: bool,
+        mut progress: F,
+    ) -> anyhow::Result<Vec<(VariableName, anyhow::Error)>>
+    where
+        F: FnMut(usize, &VariableName, usize, usize),
+    {
+        let mut errors = Vec::new();
+        for (index, entry) in group.entries.iter().enumerate() {
+            let total = entry.data.len();
+            progress(index, &entry.name, 0, total);
+
+            match self.send_file_entry(entry) {
+                Ok(()) => progress(index, &entry.name, total, total),
+                Err(err) if stop_on_error => return Err(err),
+                Err(err) => {
+                    println!("[Failed to send {}: {err}]", entry.name);
+                    errors.push((entry.name.clone(), err));
+                }
+            }
+        }
+
+        Ok(errors)
+    }
+
+    /// Sends an app from a Flash file, straight to the archive where apps are kept. Fails
+    /// with [`NotAnApp`] for other Flash files, like OSes.
+    pub fn send_app(&mut self, file: &FlashFile) -> anyhow::Result<()> {
+        if file.data_type != FLASH_APP {
+            return Err(NotAnApp {
+                name: file.name.clone(),
+                data_type: file.data_type,
+            }
+            .into());
+        }
+
+        let data = file.data();
+        let header = Variable {
+            name: VariableName::from(file.name.as_str()),
+            attributes: vec![
+                VariableAttribute::Size(data.len() as u32),
+                VariableAttribute::Kind(VariableKind::App as u32),
+                VariableAttribute::Version(0),
+                VariableAttribute::Archived(true),
+            ],
+            unavailable: Vec::new(),
+        };
+
+        self.send_variable(header, VariableContents::App(data))
+    }
+
+    /// Sends every variable in the group file at `path`, such as one written by [`Self::backup`].
+    /// It's decompressed first if the path ends in `.gz`.
+    pub fn restore(
+        &mut self,
+        path: &Path,
+        stop_on_error: bool,
+    ) -> anyhow::Result<Vec<(VariableName, anyhow::Error)>> {
+        let group = backup::read_group(path)?;
+        self.send_group_with_progress(&group, stop_on_error, |_, _, _, _| {})
+    }
+
+    /// Sends a group file member with its data exactly as it's stored in the file.
+    fn send_file_entry(&mut self, entry: &TiFileEntry) -> anyhow::Result<()> {
+        let kind = VariableKind::from_file_type_byte(entry.type_id)
+            .ok_or(UnknownVariableKindError(entry.type_id as u32))?;
+        let header = Variable {
+            name: entry.name.clone(),
+            attributes: vec![
+                VariableAttribute::Size(entry.data.len() as u32),
+                VariableAttribute::Kind(kind as u32),
+                VariableAttribute::Version(entry.version),
+                VariableAttribute::Archived(entry.archived),
+                VariableAttribute::Locked(false),
+            ],
+            unavailable: Vec::new(),
+        };
+
+        self.send_variable_payload(header, kind as u32, entry.data.clone())
+    }
+
+    /// Presses a single key on the calculator.
+    pub fn send_key(&mut self, key: KeyCode) -> anyhow::Result<()> {
+        VirtualPacket::Execute(ExecuteAction::Key(key)).send(self)?;
+        VirtualPacket::receive_acknowledge(self, VirtualPacketKind::DataAcknowledge)
+    }
+
+    /// Types out text by pressing the key for each character, see [`KeyCode::for_char`]. Nothing
+    /// is sent if any character doesn't have a key.
+    pub fn type_text(&mut self, text: &str) -> anyhow::Result<()> {
+        let keys = text
+            .chars()
+            .map(|c| KeyCode::for_char(c).ok_or(NoKeyForChar(c)))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.send_keys(&keys)
+    }
+
+    /// Presses each key in order. The calculator replies `Busy` if keys arrive faster than it
+    /// can handle them, in which case the connection is recovered (see [`Self::recover`]), the
+    /// delay between keys is increased and the key retried.
+    pub fn send_keys(&mut self, keys: &[KeyCode]) -> anyhow::Result<()> {
+        const MIN_DELAY: Duration = Duration::from_millis(25);
+        const MAX_DELAY: Duration = Duration::from_millis(1600);
+
+        let mut delay = Duration::ZERO;
+        for &key in keys {
+            thread::sleep(delay);
+            loop {
+                match self.send_key(key) {
+                    Err(err)
+                        if delay < MAX_DELAY
+                            && matches!(err.downcast_ref(), Some(DeviceError::Busy)) =>
+                    {
+                        delay = (delay * 2).clamp(MIN_DELAY, MAX_DELAY);
+                        println!(
+                            "Calculator is busy, waiting {}ms between keys",
+                            delay.as_millis()
+                        );
+                        self.recover()?;
+                        thread::sleep(delay);
+                    }
+                    result => break result?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) -> anyhow::Result<()> {
+        self.negotiate_packet_size(self.max_raw_packet_size)?;
+
+        VirtualPacket::SetMode(mode).send(self)?;
+        VirtualPacket::receive_acknowledge(self, VirtualPacketKind::SetModeAcknowledge)
+    }
+
+    /// The ready check done before the first operation of a session, which wakes the
+    /// calculator (see [`Self::wake`]) and sets the mode to [`Mode::Normal`]. Operations after
+    /// it don't need their own, so this only has to be done again after reconnecting.
+    pub fn ready_check(&mut self) -> anyhow::Result<()> {
+        self.wake()?;
+        self.set_mode(Mode::Normal)
+    }
+
+    /// Makes sure the calculator is awake. A CE that has been idle for a while can miss the
+    /// first packet sent to it, so this pings it with a short timeout first, and if that goes
+    /// unanswered, clears out anything left over and pings again with the normal timeout.
+    pub fn wake(&mut self) -> anyhow::Result<()> {
+        let timeout = mem::replace(&mut self.timeout, WAKE_TIMEOUT);
+        let result = self.ping();
+        self.timeout = timeout;
+
+        if let Err(err) = result {
+            println!("[Calculator didn't answer ({err}), it may be asleep. Trying again]");
+            self.recover()?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets a token another thread can use to stop a read that's waiting on the calculator,
+    /// which then fails with [`TransferError::Cancelled`].
+    ///
+    /// Once a token has been handed out, reads wait in slices of [`CANCEL_POLL_INTERVAL`] so
+    /// they can notice the cancellation, instead of one read for the whole timeout. Each of
+    /// those reads asks for a single USB packet, so one that times out can't have dropped
+    /// part of what the calculator sent.
+    pub fn cancel_token(&mut self) -> CancelToken {
+        self.cancel.get_or_insert_with(CancelToken::default).clone()
+    }
+
+    pub fn send(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        self.send_with_timeout(bytes, self.timeout)
+    }
+
+    /// Like [`Self::send`], but with a different timeout than the configured one for this
+    /// write only.
+    pub fn send_with_timeout(&mut self, bytes: &[u8], timeout: Duration) -> anyhow::Result<()> {
+        if self.debug_transfer {
+            writeln!(self.debug_output, "Sending {} bytes...", bytes.len())?;
+            writeln!(self.debug_output, "{bytes:02x?}")?;
+        }
+
+        let start = Instant::now();
+        self.device
+            .write_bulk(self.write_endpoint, bytes, timeout)
+            .map_err(TransferError::from)?;
+        if let Some(stats) = &mut self.stats {
+            stats.bytes_sent += bytes.len() as u64;
+            stats.elapsed += start.elapsed();
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for Calculator {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.debug_transfer {
+            writeln!(self.debug_output, "Receiving {} bytes...", buf.len())?;
+        }
+
+        // Keep reading until the whole request can be satisfied, instead of handing back
+        // whatever a single bulk read happened to return
+        let bytes_requested = buf.len().min(self.max_raw_packet_size as usize);
+        // A bulk read that times out loses whatever it had received, so reads that are meant
+        // to time out ask for one USB packet at a time, which arrives whole or not at all
+        let chunk_size = match self.cancel {
+            Some(_) => self.max_packet_size as usize,
+            None => self.read_buffer_size,
+        };
+        let mut chunk = vec![0; chunk_size];
+        let start = Instant::now();
+        let deadline = start + self.timeout;
+        while self.buffer.len() < bytes_requested {
+            let timeout = match &self.cancel {
+                Some(cancel) if cancel.take() => return Err(TransferError::Cancelled.into()),
+                // A zero timeout would wait forever
+                Some(_) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .clamp(Duration::from_millis(1), CANCEL_POLL_INTERVAL),
+                None => self.timeout,
+            };
+
+            let bytes_read = match self
+                .device
+                .read_bulk(self.read_endpoint, &mut chunk, timeout)
+            {
+                Ok(0) => break,
+                Ok(bytes) => bytes,
+                Err(rusb::Error::Timeout) if Instant::now() < deadline => continue,
+                // Return what's already been read, the error will come up again next time
+                Err(_) if !self.buffer.is_empty() => break,
+                Err(err) => return Err(TransferError::from(err).into()),
+            };
+            self.buffer.extend_from_slice(&chunk[..bytes_read]);
+            if let Some(stats) = &mut self.stats {
+                stats.bytes_received += bytes_read as u64;
+            }
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.elapsed += start.elapsed();
+        }
+
+        let bytes_read = bytes_requested.min(self.buffer.len());
+        buf[..bytes_read].copy_from_slice(&self.buffer[..bytes_read]);
+        self.buffer.drain(..bytes_read);
+
+        if self.debug_transfer {
+            writeln!(self.debug_output, "{:02x?}", &buf[..bytes_read])?;
+        }
+
+        Ok(bytes_read)
+    }
+}
+
+/// Copies a variable from one calculator to another, keeping whether it's archived or locked.
+/// The contents are forwarded byte for byte, without being decoded. Fails with
+/// [`KindNotSupported`](model::KindNotSupported) before anything is transferred if `dst`
+/// can't store it.
+pub fn transfer_variable(
+    src: &mut Calculator,
+    dst: &mut Calculator,
+    name: impl Into<VariableName>,
+    kind: VariableKind,
+) -> anyhow::Result<()> {
+    dst.model.check_supported(kind)?;
+
+    let header = src.request_variable_header(name.into(), Some(kind as u32), BACKUP_ATTRIBUTES)?;
+    let payload = src.receive_variable_contents()?;
+
+    dst.send_variable_payload(header, kind as u32, payload)
+}
+
+/// The archived variables out of `variables`, largest first.
+fn archived_by_size(mut variables: Vec<Variable>) -> Vec<Variable> {
+    variables.retain(|variable| variable.location() == Some(StorageLocation::Archive));
+    variables.sort_by_key(|variable| Reverse(variable.size()));
+    variables
+}
+
+/// The ID in an ID list's contents, which is bytes 4 to 10 with the two bytes at 9 and 10
+/// swapped, the same as libticalcs reads it.
+fn decode_id_list(contents: &[u8]) -> Result<String, raw::InvalidPayload> {
+    let mut id = contents.get(4..11).ok_or(raw::InvalidPayload)?.to_vec();
+    id.swap(5, 6);
+
+    Ok(id.iter().map(|byte| format!("{byte:02X}")).collect())
+}
+
+/// The header for a new variable in RAM and unlocked. The size is that of the payload that's
+/// actually sent, including any length prefix.
+fn raw_variable_header(name: VariableName, contents: &VariableContents, version: u8) -> Variable {
+    Variable {
+        name,
+        attributes: vec![
+            VariableAttribute::Size(contents.payload_len() as u32),
+            VariableAttribute::Kind(contents.kind_id()),
+            VariableAttribute::Version(version),
+            VariableAttribute::Archived(false),
+            VariableAttribute::Locked(false),
+        ],
+        unavailable: Vec::new(),
+    }
+}
+
+fn find_calculator() -> anyhow::Result<Option<(Device<GlobalContext>, Model)>> {
+    Ok(rusb::devices()?
+        .iter()
+        .find_map(|device| calculator_model(&device).map(|model| (device, model))))
+}
+
+/// The model of calculator `device` is, or `None` if it isn't one.
+fn calculator_model(device: &Device<GlobalContext>) -> Option<Model> {
+    let descriptor = device.device_descriptor().unwrap();
+    if descriptor.vendor_id() != TI_VENDOR {
+        return None;
+    }
+
+    Model::from_product_id(descriptor.product_id())
+}
+
+fn gif_frame(image: image::RgbImage, delay: Delay) -> Frame {
+    Frame::from_parts(DynamicImage::ImageRgb8(image).into_rgba8(), 0, 0, delay)
+}
+
+fn _take_screenshot<P>(calculator: &mut Calculator, output_path: P) -> anyhow::Result<()>
+where
+    P: AsRef<Path>,
+{
+    let (width, height, screenshot) = calculator.screenshot_raw()?;
+    screenshot.to_image(width, height)?.save(output_path)?;
+
+    Ok(())
+}
+
+/// The name to show for the calculator. Not every calculator has an ASCII product string, so
+/// if it couldn't be read this falls back to [`Model::name`]. Otherwise it's only used as a
+/// cross-check against the model from the product ID.
+fn product_name(model: Model, product: rusb::Result<String>) -> String {
+    match product {
+        Ok(product) => {
+            if let Some(named) = Model::from_product_string(&product) {
+                if named != model {
+                    println!(
+                        "[Product string suggests {named:?}, but the product ID says {model:?}]"
+                    );
+                }
+            }
+            product
+        }
+        Err(err) => {
+            println!("[Couldn't read the product string: {err}]");
+            model.name().to_owned()
+        }
+    }
+}
 
 fn main() -> anyhow::Result<()> {
-    let calculator = find_calculator()?
+    let (calculator, model) = find_calculator()?
         .with_context(|| "No calculator found")
         .unwrap();
     let descriptor = calculator.device_descriptor()?;
-    let mut handle = calculator.open()?;
+    let handle = calculator.open()?;
+    let product = product_name(model, handle.read_product_string_ascii(&descriptor));
     println!(
-        "Product string: {}\nVersion: {}",
-        handle.read_product_string_ascii(&descriptor)?,
+        "Product: {product}\nVersion: {}",
         descriptor.device_version()
     );
 
-    handle.claim_interface(0)?;
+    if model.protocol() == Protocol::Dbus {
+        let mut calculator = LegacyCalculator::new(handle, model, None, Duration::from_secs(10))?;
+        for variable in calculator.request_directory()? {
+            println!("{variable:?}");
+        }
 
-    let mut calculator = Calculator::new(handle, Duration::from_secs(10))?;
-    calculator.set_mode(Mode::Normal)?;
+        return Ok(());
+    }
 
-    let str = String::from("Test");
-    calculator.send_variable(
-        Variable {
-            name: String::from("Str1"),
-            attributes: vec![
-                VariableAttribute::Size(str.len() as u32),
-                VariableAttribute::Kind(0xf0070004),
-                VariableAttribute::Version(0),
-                VariableAttribute::Archived(false),
-                VariableAttribute::Locked(false),
-            ],
-        },
-        VariableContents::String(str),
-    )?;
+    let mut calculator = Calculator::new(handle, model, Duration::from_secs(10))?;
+    calculator.ready_check()?;
+
+    calculator.send_raw_variable("Str1", VariableKind::String, b"Test", None)?;
 
-    // let var = calculator.request_variable("Str1".to_owned())?;
+    // let var = calculator.request_variable("Str1", VariableKind::String)?;
     // match var {
     //     VariableContents::Image(img) => fs::write("img.bin", img)?,
     //     VariableContents::String(s) => fs::write("str.txt", s)?,
@@ -361,9 +1557,1114 @@ fn main() -> anyhow::Result<()> {
     // ])?;
     // let mut s = String::new();
     // for variable in variables {
-    //     s.push_str(&format!("{variable:02x?}\n"));
+    //     s.push_str(&format!("{variable}\n"));
     // }
     // fs::write("variables.txt", s)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufReader;
+
+    use image::{codecs::gif::GifDecoder, AnimationDecoder};
+
+    use super::*;
+    use crate::{
+        dusb::{FirmwareVersion, OsMode, Unavailable},
+        mock,
+        tifiles::FlashPage,
+        util::u32_from_bytes,
+    };
+
+    /// The variables requested, with the kind sent along with each one if there was one.
+    fn requested_variables(mock: &mock::Mock) -> Vec<(VariableName, Option<u32>)> {
+        mock.received()
+            .into_iter()
+            .filter(|(kind, _)| *kind == VirtualPacketKind::RequestVariable as u16)
+            .map(|(_, payload)| {
+                let length = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+                let name = VariableName(payload[2..2 + length].to_vec());
+                // The kind is the only attribute ever specified, right before the last 2 bytes
+                let kind = (payload[payload.len() - 10..payload.len() - 6] == [0, 0x11, 0, 4])
+                    .then(|| u32_from_bytes(&payload[payload.len() - 6..payload.len() - 2]));
+                (name, kind)
+            })
+            .collect()
+    }
+
+    fn variable(kind: VariableKind, data: &[u8]) -> Vec<VariableAttribute> {
+        vec![
+            VariableAttribute::Size(data.len() as u32),
+            VariableAttribute::Kind(kind as u32),
+            VariableAttribute::Version(0),
+            VariableAttribute::Archived(false),
+        ]
+    }
+
+    #[test]
+    fn raw_variable_size_matches_payload() {
+        for (kind, data) in [
+            (VariableKind::String, &b"Test"[..]),
+            (VariableKind::Program, &[0xde, 0x2a, 0x48, 0x2a][..]),
+            (VariableKind::AppVar, &[][..]),
+            (VariableKind::Image, &[1, 2, 3][..]),
+        ] {
+            let contents = VariableContents::from_data(kind, data).unwrap();
+            let header = raw_variable_header(VariableName::from("A"), &contents, 0);
+
+            assert_eq!(
+                header.size(),
+                Some(contents.into_payload().len() as u32),
+                "{kind:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn version_is_sent_in_header() {
+        let contents = VariableContents::Program(vec![0xde, 0x2a]);
+        let header = raw_variable_header(VariableName::from("A"), &contents, 3);
+        let payload = Vec::try_from(VirtualPacket::RequestToSend(header)).unwrap();
+
+        // The Version attribute's ID, length and value
+        assert!(payload
+            .windows(5)
+            .any(|attribute| attribute == [0, 0x08, 0, 1, 3]));
+    }
+
+    #[test]
+    fn verified_send_compares_read_back() {
+        for (stored, matches) in [(&b"Test"[..], true), (&b"Tost"[..], false)] {
+            let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+            let contents = VariableContents::from_data(VariableKind::String, b"Test").unwrap();
+            let header = raw_variable_header(VariableName::from("Str1"), &contents, 0);
+            let stored = VariableContents::from_data(VariableKind::String, stored)
+                .unwrap()
+                .into_payload();
+            mock.reply(vec![mock::data_acknowledge()]);
+            mock.reply(vec![mock::data_acknowledge()]);
+            // The end of transmission gets no reply, the read back request does
+            mock.reply(vec![]);
+            mock.reply(vec![
+                mock::variable_header(
+                    "Str1",
+                    vec![
+                        VariableAttribute::Archived(false),
+                        VariableAttribute::Locked(false),
+                    ],
+                ),
+                mock::variable_contents(&stored),
+            ]);
+
+            let result = calculator.send_variable_verified(header, contents);
+
+            if matches {
+                result.unwrap();
+            } else {
+                assert!(result.unwrap_err().is::<VerificationFailed>());
+            }
+        }
+    }
+
+    #[test]
+    fn archived_variables_largest_first() {
+        let variable = |name: &str, size: u32, archived: bool| Variable {
+            name: VariableName::from(name),
+            attributes: vec![
+                VariableAttribute::Size(size),
+                VariableAttribute::Archived(archived),
+            ],
+            unavailable: Vec::new(),
+        };
+        let variables = vec![
+            variable("A", 10, true),
+            variable("B", 500, false),
+            variable("C", 300, true),
+            variable("D", 20, true),
+        ];
+
+        let names: Vec<_> = archived_by_size(variables)
+            .into_iter()
+            .map(|variable| variable.name.to_string())
+            .collect();
+        assert_eq!(names, ["C", "D", "A"]);
+    }
+
+    #[test]
+    fn product_name_falls_back_to_model() {
+        let name = product_name(Model::Ti84PlusCe, Err(rusb::Error::Pipe));
+        assert_eq!(name, "TI-84 Plus CE");
+
+        // The product string is kept even if it disagrees with the product ID
+        let name = product_name(Model::Ti84Plus, Ok("TI-84 Plus CE".to_string()));
+        assert_eq!(name, "TI-84 Plus CE");
+    }
+
+    #[test]
+    fn debug_output_is_captured() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let output = mock::SharedBuffer::default();
+        calculator.debug_transfer = true;
+        calculator.debug_output = Box::new(output.clone());
+
+        calculator.send(&[1, 2, 0xff]).unwrap();
+        mock.send_raw(&[0xab, 0xcd]);
+        let mut buf = [0; 2];
+        calculator.read_exact(&mut buf).unwrap();
+
+        assert_eq!(
+            output.contents(),
+            "Sending 3 bytes...\n[01, 02, ff]\nReceiving 2 bytes...\n[ab, cd]\n"
+        );
+    }
+
+    #[test]
+    fn read_spans_bulk_reads() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.set_read_limit(3);
+        mock.send_raw(&[1, 2, 3, 4, 5]);
+        let reads = mock.read_lengths().len();
+
+        let mut buf = [0; 5];
+        assert_eq!(calculator.read(&mut buf).unwrap(), 5);
+        assert_eq!(buf, [1, 2, 3, 4, 5]);
+        assert_eq!(mock.read_lengths().len() - reads, 2);
+    }
+
+    #[test]
+    fn flush_discards_stale_input() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        calculator.buffer.extend_from_slice(&[1, 2, 3]);
+        mock.send_raw(&[4, 5, 6]);
+
+        calculator.flush().unwrap();
+        assert!(calculator.buffer.is_empty());
+        assert_eq!(mock.pending_len(), 0);
+
+        // Nothing left to read at all, which times out
+        calculator.flush().unwrap();
+    }
+
+    #[test]
+    fn batch_continues_past_failed_request() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let string = |name, data: &[u8]| {
+            vec![
+                mock::variable_header(name, variable(VariableKind::String, data)),
+                mock::variable_contents(data),
+            ]
+        };
+        mock.reply(string("Str1", &[1, 0, b'A']));
+        mock.reply(vec![mock::error(DeviceError::InvalidName)]);
+        mock.reply(string("Str3", &[1, 0, b'C']));
+
+        let names =
+            ["Str1", "Str2", "Str3"].map(|name| (VariableName::from(name), VariableKind::String));
+        let results = calculator.request_variables(&names);
+
+        assert_eq!(results.len(), 3);
+        assert!(matches!(&results[0], Ok(VariableContents::String(string)) if string == "A"));
+        let err = results[1].as_ref().unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(DeviceError::InvalidName)));
+        assert!(matches!(&results[2], Ok(VariableContents::String(string)) if string == "C"));
+    }
+
+    #[test]
+    fn unplugged_calculator_isnt_connected() {
+        let (calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        assert!(calculator.is_connected());
+
+        mock.unplug(false);
+        assert!(!calculator.is_connected());
+        assert!(mock.writes().is_empty());
+    }
+
+    #[test]
+    fn variable_count() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![
+            mock::variable_header("Str1", Vec::new()),
+            mock::variable_header("PROG", Vec::new()),
+            mock::variable_header("A", Vec::new()),
+            mock::end_of_transmission(),
+        ]);
+
+        assert_eq!(calculator.variable_count().unwrap(), 3);
+    }
+
+    #[test]
+    fn reconnects_after_reset() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.unplug(true);
+        mock.reply(vec![mock::virtual_packet(
+            VirtualPacketKind::SetModeAcknowledge,
+            &[],
+        )]);
+
+        let mut attempts = 0;
+        calculator
+            .with_reconnect(|calculator| {
+                attempts += 1;
+                calculator.ping()
+            })
+            .unwrap();
+        assert_eq!(attempts, 2);
+        assert!(calculator.is_connected());
+
+        // Gone for good this time
+        mock.unplug(false);
+        let err = calculator.with_reconnect(Calculator::ping).unwrap_err();
+        assert!(err.is::<CalculatorReset>());
+        assert!(TransferError::is_disconnect(&err));
+    }
+
+    #[test]
+    fn stats_count_bytes() {
+        let mock = mock::Mock::new();
+        let mut calculator = mock.connect(
+            Calculator::builder(Model::Ti84PlusCe)
+                .timeout(mock::TIMEOUT)
+                .collect_stats(true),
+        );
+        calculator.stats = Some(TransferStats::default());
+        let data = [4, 0, b'T', b'e', b's', b't'];
+        let reply = vec![
+            mock::variable_header("Str1", variable(VariableKind::String, &data)),
+            mock::variable_contents(&data),
+        ];
+        // Each reply arrives as a single raw packet, after the request's acknowledgment
+        let received = 7 + reply.iter().map(|packet| 5 + packet.len()).sum::<usize>();
+        mock.reply(reply);
+
+        calculator
+            .request_variable("Str1", VariableKind::String)
+            .unwrap();
+
+        let stats = calculator.stats.unwrap();
+        let sent: usize = mock.writes().iter().map(Vec::len).sum();
+        assert_eq!(stats.bytes_sent, sent as u64);
+        assert_eq!(stats.bytes_received, received as u64);
+    }
+
+    #[test]
+    fn writes_use_configured_timeout() {
+        let mock = mock::Mock::new();
+        let mut calculator = mock
+            .connect(Calculator::builder(Model::Ti84PlusCe).timeout(Duration::from_millis(1234)));
+
+        calculator.send(&[0]).unwrap();
+        calculator
+            .send_with_timeout(&[0], Duration::from_millis(10))
+            .unwrap();
+
+        assert_eq!(
+            mock.write_timeouts(),
+            [Duration::from_millis(1234), Duration::from_millis(10)]
+        );
+    }
+
+    #[test]
+    fn refused_header_stops_send() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![mock::error(DeviceError::OutOfMemory)]);
+
+        let err = calculator
+            .send_raw_variable("Str1", VariableKind::String, b"Test", None)
+            .unwrap_err();
+
+        assert!(matches!(err.downcast_ref(), Some(DeviceError::OutOfMemory)));
+        let kinds: Vec<_> = mock.received().into_iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [VirtualPacketKind::RequestToSend as u16]);
+    }
+
+    #[test]
+    fn gif_has_requested_frames() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("screen.gif");
+        let (mut calculator, mock) = mock::connect(Model::Ti84Plus);
+        for _ in 0..3 {
+            mock.reply(vec![mock::parameter_response(vec![
+                Parameter::ScreenWidth(96),
+                Parameter::ScreenHeight(64),
+                Parameter::ScreenContents(Screenshot::Raw(vec![0x55; 768])),
+            ])]);
+        }
+
+        calculator.record_gif(3, 10, &path).unwrap();
+
+        let decoder = GifDecoder::new(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let frames = decoder.into_frames().collect_frames().unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].buffer().dimensions(), (96, 64));
+    }
+
+    #[test]
+    fn picture_saved_as_png_or_ti_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let (mut calculator, mock) = mock::connect(Model::Ti84Plus);
+        // A monochrome picture with only its top left pixel set
+        let mut data = vec![0; 756];
+        data[0] = 0x80;
+        let mut payload = (data.len() as u16).to_le_bytes().to_vec();
+        payload.extend_from_slice(&data);
+        for _ in 0..2 {
+            mock.reply(vec![
+                mock::variable_header("Pic1", variable(VariableKind::Picture, &payload)),
+                mock::variable_contents(&payload),
+            ]);
+        }
+
+        let png = calculator
+            .pull_picture("Pic1", &directory.path().join("Pic1.png"))
+            .unwrap();
+        let ti_file = calculator
+            .pull_picture("Pic1", &directory.path().join("Pic1"))
+            .unwrap();
+
+        let image = image::open(&png).unwrap().into_rgb8();
+        assert_eq!(image.dimensions(), (96, 63));
+        assert_eq!(image.get_pixel(0, 0), &image::Rgb([0, 0, 0]));
+        assert_eq!(image.get_pixel(1, 0), &image::Rgb([0xff, 0xff, 0xff]));
+
+        assert_eq!(ti_file.extension(), Some("8xi".as_ref()));
+        let file = TiFile::read(&std::fs::read(&ti_file).unwrap()).unwrap();
+        assert_eq!(
+            file.entries[0].type_id,
+            VariableKind::Picture.file_type_byte()
+        );
+        assert_eq!(file.entries[0].data, payload);
+    }
+
+    #[test]
+    fn only_apps_are_listed() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let entry = |size: u32, kind: VariableKind| {
+            vec![
+                VariableAttribute::Size(size),
+                VariableAttribute::Kind(kind as u32),
+            ]
+        };
+        mock.reply(vec![
+            mock::variable_header("CabriJr", entry(16 * 1024 + 1, VariableKind::App)),
+            mock::variable_header("Str1", entry(6, VariableKind::String)),
+            mock::variable_header("Inequalz", entry(100, VariableKind::App)),
+            mock::end_of_transmission(),
+        ]);
+
+        let apps = calculator.list_apps().unwrap();
+
+        assert_eq!(
+            apps,
+            [
+                AppInfo {
+                    name: VariableName::from("CabriJr"),
+                    size: 16 * 1024 + 1,
+                    pages: Some(2),
+                },
+                AppInfo {
+                    name: VariableName::from("Inequalz"),
+                    size: 100,
+                    pages: Some(1),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn full_directory_has_every_attribute() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let attributes = vec![
+            VariableAttribute::Size(6),
+            VariableAttribute::Kind(VariableKind::String as u32),
+            VariableAttribute::Archived(false),
+            VariableAttribute::AppVarSource(0),
+            VariableAttribute::Version(0),
+            VariableAttribute::Kind2(VariableKind::String as u32),
+            VariableAttribute::Archived2(false),
+            VariableAttribute::Locked(false),
+        ];
+        // B's size, with AppVarSource marked as invalid
+        let unavailable = [
+            0, 1, b'B', 0, 0, 2, 0, 0x01, 0, 0, 4, 0, 0, 0, 5, 0, 0x05, 1,
+        ];
+        mock.reply(vec![
+            mock::variable_header("A", attributes),
+            mock::virtual_packet(VirtualPacketKind::VariableHeader, &unavailable),
+            mock::end_of_transmission(),
+        ]);
+
+        let variables = calculator.full_directory().unwrap();
+
+        let requested = &mock.received()[0].1;
+        assert_eq!(
+            requested[..4],
+            [0, 0, 0, VariableAttributeKind::ALL.len() as u8]
+        );
+        let kinds: Vec<_> = variables[0]
+            .attributes
+            .iter()
+            .map(VariableAttributeKind::from)
+            .collect();
+        assert_eq!(kinds, VariableAttributeKind::ALL);
+        assert_eq!(variables[1].size(), Some(5));
+        assert_eq!(
+            variables[1].unavailable,
+            [Unavailable { id: 0x05, code: 1 }]
+        );
+    }
+
+    #[test]
+    fn asleep_calculator_is_woken() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.ignore(1);
+
+        let start = Instant::now();
+        calculator.wake().unwrap();
+
+        // The first ping waited out the short timeout, then the second one was answered
+        assert!(start.elapsed() >= WAKE_TIMEOUT);
+        let kinds: Vec<_> = mock.writes().iter().map(|write| write[4]).collect();
+        assert_eq!(kinds, [1, 1]);
+        assert_eq!(calculator.timeout, mock::TIMEOUT);
+    }
+
+    #[test]
+    fn locked_variable_or_calculator() {
+        let kind = vec![VariableAttribute::Kind(VariableKind::String as u32)];
+        for (listing, locked_calculator) in [
+            (vec![mock::variable_header("Str1", kind)], false),
+            (Vec::new(), true),
+        ] {
+            let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+            mock.reply(vec![mock::error(DeviceError::VariableUnwritable)]);
+            mock.reply([listing, vec![mock::end_of_transmission()]].concat());
+
+            let err = calculator
+                .send_raw_variable("Str1", VariableKind::String, b"Test", None)
+                .unwrap_err();
+
+            assert_eq!(err.is::<CalculatorLocked>(), locked_calculator);
+            assert_eq!(
+                matches!(err.downcast_ref(), Some(DeviceError::VariableUnwritable)),
+                !locked_calculator
+            );
+        }
+    }
+
+    #[test]
+    fn variable_range() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let payload = [5, 0, 0xde, 0x2a, 0x48, 0x49, 0x2a];
+        for _ in 0..2 {
+            mock.reply(vec![
+                mock::variable_header("PROG", variable(VariableKind::Program, &payload)),
+                mock::variable_contents(&payload),
+            ]);
+        }
+
+        let range = calculator.read_variable_range("PROG", VariableKind::Program, 0, 3);
+        assert_eq!(range.unwrap(), [0xde, 0x2a, 0x48]);
+        // Past the end of the data
+        let range = calculator.read_variable_range("PROG", VariableKind::Program, 3, 10);
+        assert_eq!(range.unwrap(), [0x49, 0x2a]);
+    }
+
+    #[test]
+    fn error_during_listing() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![
+            mock::variable_header("Str1", Vec::new()),
+            mock::error(DeviceError::OutOfMemory),
+        ]);
+
+        let err = calculator.request_directory(&[]).unwrap_err();
+
+        assert!(matches!(err.downcast_ref(), Some(DeviceError::OutOfMemory)));
+        assert!(err.to_string().contains("listing variables"));
+    }
+
+    #[test]
+    fn variable_attributes() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![
+            mock::variable_header(
+                "Str1",
+                vec![VariableAttribute::Size(6), VariableAttribute::Locked(true)],
+            ),
+            mock::variable_contents(&[4, 0, b'T', b'e', b's', b't']),
+        ]);
+
+        let attributes = calculator
+            .variable_attributes(
+                "Str1",
+                &[VariableAttributeKind::Size, VariableAttributeKind::Locked],
+            )
+            .unwrap();
+
+        assert!(matches!(
+            attributes[..],
+            [VariableAttribute::Size(6), VariableAttribute::Locked(true)]
+        ));
+        // The contents were received too, leaving nothing behind
+        assert_eq!(mock.pending_len(), 0);
+        assert_eq!(
+            requested_variables(&mock),
+            [(VariableName::from("Str1"), None)]
+        );
+    }
+
+    #[test]
+    fn screenshot_marked_invalid() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        // The width and height, then ScreenContents marked as invalid with code 0x01
+        let payload = [
+            0, 3, 0, 0x1e, 0, 0, 2, 0x01, 0x40, 0, 0x1f, 0, 0, 2, 0, 0xf0, 0, 0x22, 1,
+        ];
+        mock.reply(vec![mock::virtual_packet(
+            VirtualPacketKind::ParameterResponse,
+            &payload,
+        )]);
+
+        let err = calculator.screenshot_raw().unwrap_err();
+        assert!(err.is::<ScreenshotUnsupported>());
+    }
+
+    #[test]
+    fn stopping_listing_early_stays_in_sync() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![
+            mock::variable_header("Str1", Vec::new()),
+            mock::variable_header("Str2", Vec::new()),
+            mock::variable_header("Str3", Vec::new()),
+            mock::end_of_transmission(),
+        ]);
+
+        let mut seen = Vec::new();
+        calculator
+            .request_directory_with(&[], |variable| {
+                seen.push(variable.name);
+                ControlFlow::Break(())
+            })
+            .unwrap();
+
+        assert_eq!(seen, [VariableName::from("Str1")]);
+        assert_eq!(mock.pending_len(), 0);
+        calculator.ping().unwrap();
+    }
+
+    #[test]
+    fn request_specifies_kind() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let data = [4, 0, b'T', b'e', b's', b't'];
+        mock.reply(vec![
+            mock::variable_header("Str1", variable(VariableKind::String, &data)),
+            mock::variable_contents(&data),
+        ]);
+
+        calculator
+            .request_variable("Str1", VariableKind::String)
+            .unwrap();
+
+        assert_eq!(
+            requested_variables(&mock),
+            [(VariableName::from("Str1"), Some(0xf0070004))]
+        );
+    }
+
+    #[test]
+    fn set_mode_error_is_device_error() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![mock::error(DeviceError::WrongMode)]);
+
+        let err = calculator.set_mode(Mode::Normal).unwrap_err();
+
+        assert!(matches!(err.downcast_ref(), Some(DeviceError::WrongMode)));
+        assert!(!err.is::<vtl::WrongPacketKind>());
+    }
+
+    #[test]
+    fn ready_check() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![mock::virtual_packet(
+            VirtualPacketKind::SetModeAcknowledge,
+            &[],
+        )]);
+
+        calculator.ready_check().unwrap();
+
+        let size = calculator.max_raw_packet_size.to_be_bytes();
+        assert_eq!(
+            mock.writes(),
+            [
+                // Waking it, then renegotiating before setting the mode
+                mock::raw_packet(1, &size),
+                mock::raw_packet(1, &size),
+                vec![0, 0, 0, 16, 4, 0, 0, 0, 10, 0, 0x01, 0, 3, 0, 1, 0, 0, 0, 0, 0x7d, 0xd0],
+                // Acknowledging SetModeAcknowledge
+                mock::raw_packet(5, &[0xe0, 0x00]),
+            ]
+        );
+        assert_eq!(mock.pending_len(), 0);
+    }
+
+    #[test]
+    fn ping_responsive() {
+        let (mut calculator, _mock) = mock::connect(Model::Ti84PlusCe);
+        calculator.ping().unwrap();
+    }
+
+    #[test]
+    fn ping_silent_times_out() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.ignore(1);
+
+        let err = calculator.ping().unwrap_err();
+        let err = err.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn interrupted_backup_only_pulls_remaining_variables() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("backup.8xg");
+
+        // The first run saved Str1 before it was interrupted
+        let mut partial = PartialBackup::open(&PartialBackup::path_for(&path)).unwrap();
+        partial
+            .record(TiFileEntry {
+                name: VariableName::from("Str1"),
+                type_id: VariableKind::String.file_type_byte(),
+                version: 0,
+                archived: false,
+                data: vec![1, 0, b'A'],
+            })
+            .unwrap();
+        drop(partial);
+
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let kind = |kind: VariableKind| vec![VariableAttribute::Kind(kind as u32)];
+        mock.reply(vec![
+            mock::variable_header("Str1", kind(VariableKind::String)),
+            mock::variable_header("Str2", kind(VariableKind::String)),
+            mock::variable_header("PROG", kind(VariableKind::Program)),
+            mock::end_of_transmission(),
+        ]);
+        for (name, kind, data) in [
+            ("Str2", VariableKind::String, &[1, 0, b'B'][..]),
+            ("PROG", VariableKind::Program, &[1, 0, 0xde][..]),
+        ] {
+            mock.reply(vec![
+                mock::variable_header(name, variable(kind, data)),
+                mock::variable_contents(data),
+            ]);
+        }
+
+        let group = calculator.backup(&path).unwrap();
+
+        assert_eq!(
+            requested_variables(&mock),
+            [
+                (
+                    VariableName::from("Str2"),
+                    Some(VariableKind::String as u32)
+                ),
+                (
+                    VariableName::from("PROG"),
+                    Some(VariableKind::Program as u32)
+                ),
+            ]
+        );
+        let names: Vec<_> = group
+            .entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect();
+        assert_eq!(names, ["Str1", "Str2", "PROG"].map(VariableName::from));
+        assert!(path.exists());
+        assert!(!PartialBackup::path_for(&path).exists());
+    }
+
+    #[test]
+    fn tuning_ignores_unusable_allocation() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.set_allocation(4096);
+        mock.set_max_working(SAFE_PACKET_SIZE);
+
+        assert_eq!(calculator.tune_buffer_size().unwrap(), SAFE_PACKET_SIZE);
+        assert_eq!(calculator.max_raw_packet_size, SAFE_PACKET_SIZE);
+        assert!(!calculator.allow_large_buffers);
+    }
+
+    #[test]
+    fn large_buffer_is_kept_only_if_allowed() {
+        for (allow_large_buffers, expected) in [(true, 2048), (false, SAFE_PACKET_SIZE)] {
+            let mock = mock::Mock::new();
+            mock.set_allocation(2048);
+            let calculator = mock.connect(
+                Calculator::builder(Model::Ti84PlusCe)
+                    .timeout(mock::TIMEOUT)
+                    .buffer_size(4096)
+                    .allow_large_buffers(allow_large_buffers),
+            );
+
+            assert_eq!(calculator.max_raw_packet_size, expected);
+        }
+    }
+
+    #[test]
+    fn failed_tuning_restores_large_buffer_setting() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.unplug(false);
+
+        assert!(calculator.tune_buffer_size().is_err());
+        assert!(!calculator.allow_large_buffers);
+    }
+
+    #[test]
+    fn transfer_forwards_contents_unchanged() {
+        let (mut src, src_mock) = mock::connect(Model::Ti84PlusCe);
+        let (mut dst, dst_mock) = mock::connect(Model::Ti84PlusCe);
+        // Not valid UTF-8, which decoding as a string would have replaced
+        let payload = [0, 2, 0xff, 0xbb];
+        src_mock.reply(vec![
+            mock::variable_header("Str1", variable(VariableKind::String, &payload)),
+            mock::variable_contents(&payload),
+        ]);
+        dst_mock.reply(vec![mock::data_acknowledge()]);
+        dst_mock.reply(vec![mock::data_acknowledge()]);
+
+        transfer_variable(&mut src, &mut dst, "Str1", VariableKind::String).unwrap();
+
+        let received = dst_mock.received();
+        assert_eq!(
+            received[1],
+            (VirtualPacketKind::VariableContents as u16, payload.to_vec())
+        );
+    }
+
+    #[test]
+    fn group_continues_past_failed_member() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let entry = |name: &str, kind: VariableKind, data: &[u8]| TiFileEntry {
+            name: VariableName::from(name),
+            type_id: kind.file_type_byte(),
+            version: 0,
+            archived: false,
+            data: data.to_vec(),
+        };
+        let group = TiFile::new(vec![
+            entry("Str1", VariableKind::String, &[1, 0, b'A']),
+            entry("PROG", VariableKind::Program, &[2, 0, 0xde, 0x2a]),
+        ]);
+        mock.reply(vec![mock::error(DeviceError::OutOfMemory)]);
+        mock.reply(vec![mock::data_acknowledge()]);
+        mock.reply(vec![mock::data_acknowledge()]);
+
+        let mut finished = Vec::new();
+        let errors = calculator
+            .send_group_with_progress(&group, false, |index, _, sent, total| {
+                if sent == total {
+                    finished.push(index);
+                }
+            })
+            .unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, VariableName::from("Str1"));
+        assert!(matches!(
+            errors[0].1.downcast_ref(),
+            Some(DeviceError::OutOfMemory)
+        ));
+        assert_eq!(finished, [1]);
+        let contents: Vec<_> = mock
+            .received()
+            .into_iter()
+            .filter(|(kind, _)| *kind == VirtualPacketKind::VariableContents as u16)
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(contents, [vec![2, 0, 0xde, 0x2a]]);
+    }
+
+    #[test]
+    fn cancelled_read_returns_promptly() {
+        let mock = mock::Mock::new();
+        let mut calculator =
+            mock.connect(Calculator::builder(Model::Ti84PlusCe).timeout(Duration::from_secs(10)));
+        let cancel = calculator.cancel_token();
+
+        let start = Instant::now();
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        let err = calculator.read(&mut [0; 4]).unwrap_err();
+        canceller.join().unwrap();
+
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref()),
+            Some(TransferError::Cancelled)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn id_list() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let contents = [
+            0x00, 0x0e, 0x01, 0x02, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13,
+        ];
+        mock.reply(vec![
+            mock::variable_header("IDList", vec![VariableAttribute::Version(0)]),
+            mock::variable_contents(&contents),
+        ]);
+
+        assert_eq!(calculator.get_id_list().unwrap(), "0A0B0C0D0E100F");
+        assert_eq!(
+            requested_variables(&mock),
+            [(VariableName::from("IDList"), Some(ID_LIST_KIND))]
+        );
+        assert!(decode_id_list(&contents[..10]).is_err());
+    }
+
+    #[test]
+    fn send_app_sends_pages_back_to_back() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let mut file = FlashFile {
+            name: "Test".to_owned(),
+            device_type: 0x73,
+            data_type: FLASH_APP,
+            pages: vec![
+                FlashPage {
+                    page: 0,
+                    data: vec![0xde, 0xad],
+                },
+                FlashPage {
+                    page: 1,
+                    data: vec![0xbe, 0xef],
+                },
+            ],
+        };
+        mock.reply(vec![mock::data_acknowledge()]);
+        mock.reply(vec![mock::data_acknowledge()]);
+
+        calculator.send_app(&file).unwrap();
+        let received = mock.received();
+        assert_eq!(received[0].0, VirtualPacketKind::RequestToSend as u16);
+        assert_eq!(
+            received[1],
+            (
+                VirtualPacketKind::VariableContents as u16,
+                vec![0xde, 0xad, 0xbe, 0xef]
+            )
+        );
+
+        // An OS
+        file.data_type = 0x23;
+        let err = calculator.send_app(&file).unwrap_err();
+        assert!(err.is::<NotAnApp>());
+    }
+
+    #[test]
+    fn flags_survive_backup_and_restore() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let data = [1, 0, 0xde];
+        mock.reply(vec![
+            mock::variable_header(
+                "PROG",
+                vec![
+                    VariableAttribute::Size(data.len() as u32),
+                    VariableAttribute::Kind(VariableKind::Program as u32),
+                    VariableAttribute::Version(0),
+                    VariableAttribute::Archived(true),
+                    VariableAttribute::Locked(true),
+                ],
+            ),
+            mock::variable_contents(&data),
+        ]);
+        mock.reply(vec![mock::data_acknowledge()]);
+        mock.reply(vec![mock::data_acknowledge()]);
+
+        let (header, contents) = calculator.backup_variable("PROG").unwrap();
+        calculator.send_variable(header, contents).unwrap();
+
+        let received = mock.received();
+        assert_eq!(received[1].0, VirtualPacketKind::RequestToSend as u16);
+        // The Archived and Locked attributes' IDs, lengths and values
+        for attribute in [[0, 0x03, 0, 1, 1], [0, 0x41, 0, 1, 1]] {
+            assert!(received[1]
+                .1
+                .windows(attribute.len())
+                .any(|window| window == attribute));
+        }
+    }
+
+    #[test]
+    fn unknown_kind_survives_backup_and_restore() {
+        const NEW_KIND: u32 = 0xf0070099;
+        let (mut calculator, mock) = mock::connect(Model::Ti84Plus);
+        let data = [1, 2, 3];
+        mock.reply(vec![
+            mock::variable_header(
+                "NEW",
+                vec![
+                    VariableAttribute::Size(data.len() as u32),
+                    VariableAttribute::Kind(NEW_KIND),
+                ],
+            ),
+            mock::variable_contents(&data),
+        ]);
+        mock.reply(vec![mock::data_acknowledge()]);
+        mock.reply(vec![mock::data_acknowledge()]);
+
+        let (header, contents) = calculator.backup_variable("NEW").unwrap();
+        assert_eq!(contents.kind_id(), NEW_KIND);
+        calculator.send_variable(header, contents).unwrap();
+
+        let received = mock.received();
+        assert_eq!(received[1].0, VirtualPacketKind::RequestToSend as u16);
+        let kind = [0, 0x02, 0, 4, 0xf0, 0x07, 0x00, 0x99];
+        assert!(received[1]
+            .1
+            .windows(kind.len())
+            .any(|window| window == kind));
+        assert_eq!(
+            received[2],
+            (VirtualPacketKind::VariableContents as u16, data.to_vec())
+        );
+    }
+
+    #[test]
+    fn read_buffer_size() {
+        let mock = mock::Mock::new();
+        mock.connect(Calculator::builder(Model::Ti84PlusCe).timeout(mock::TIMEOUT));
+        assert!(mock.read_lengths().iter().all(|length| *length == 1024));
+
+        let mock = mock::Mock::new();
+        mock.connect(
+            Calculator::builder(Model::Ti84PlusCe)
+                .timeout(mock::TIMEOUT)
+                .read_buffer_size(2048),
+        );
+        let lengths = mock.read_lengths();
+        assert!(!lengths.is_empty());
+        assert!(lengths.iter().all(|length| *length == 2048));
+
+        let err = Calculator::builder(Model::Ti84PlusCe)
+            .read_buffer_size(0)
+            .build_with_transport(Box::new(mock::Mock::new()), mock::INTERFACE)
+            .err()
+            .unwrap();
+        assert!(err.is::<ReadBufferTooSmall>());
+    }
+
+    #[test]
+    fn boot_info() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let version = |major, minor, patch, build| FirmwareVersion {
+            major,
+            minor,
+            patch,
+            build,
+        };
+        mock.reply(vec![mock::parameter_response(vec![
+            Parameter::BootVersion(version(5, 0, 0, 89)),
+            Parameter::OsMode(OsMode::Os),
+            Parameter::OsVersion(version(5, 3, 0, 37)),
+        ])]);
+
+        let info = calculator.boot_info().unwrap();
+        assert_eq!(info.boot_version, version(5, 0, 0, 89));
+        assert_eq!(info.os_version, Some(version(5, 3, 0, 37)));
+        assert_eq!(info.mode, OsMode::Os);
+    }
+
+    #[test]
+    fn busy_key_is_retried_after_recovering() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![mock::error(DeviceError::Busy)]);
+        mock.reply(vec![mock::data_acknowledge()]);
+
+        calculator.send_keys(&[KeyCode(0x9a)]).unwrap();
+
+        let executed = mock
+            .received()
+            .iter()
+            .filter(|(kind, _)| *kind == VirtualPacketKind::Execute as u16)
+            .count();
+        assert_eq!(executed, 2);
+    }
+
+    #[test]
+    fn busy_keys_slow_down_later_keys() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.reply(vec![mock::data_acknowledge()]);
+        mock.reply(vec![mock::error(DeviceError::Busy)]);
+        mock.reply(vec![mock::error(DeviceError::Busy)]);
+        mock.reply(vec![mock::data_acknowledge()]);
+        mock.reply(vec![mock::data_acknowledge()]);
+
+        let start = Instant::now();
+        calculator
+            .send_keys(&[KeyCode(0x9a), KeyCode(0x9b), KeyCode(0x9c)])
+            .unwrap();
+
+        let keys: Vec<_> = mock
+            .received()
+            .into_iter()
+            .filter(|(kind, _)| *kind == VirtualPacketKind::Execute as u16)
+            .map(|(_, payload)| payload[3])
+            .collect();
+        assert_eq!(keys, [0x9a, 0x9b, 0x9b, 0x9b, 0x9c]);
+        // 25ms then 50ms before retrying B, and C still waits the 50ms
+        assert!(start.elapsed() >= Duration::from_millis(125));
+    }
+
+    #[test]
+    fn only_dusb_models_connect() {
+        for model in [Model::Ti83, Model::Nspire] {
+            let err = Calculator::builder(model)
+                .build_with_transport(Box::new(mock::Mock::new()), mock::INTERFACE)
+                .err()
+                .unwrap();
+            let err = err.downcast_ref::<UnsupportedProtocol>().unwrap();
+            assert_eq!(err.model, model);
+            assert_eq!(err.protocol, model.protocol());
+        }
+    }
+
+    #[test]
+    fn rgb_screenshot_left_undecoded() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let mut pixels = Box::new([0; 76800]);
+        pixels[0] = 0xf800;
+        mock.reply(vec![mock::parameter_response(vec![
+            Parameter::ScreenWidth(320),
+            Parameter::ScreenHeight(240),
+            Parameter::ScreenContents(Screenshot::Rgb(pixels)),
+        ])]);
+
+        let (width, height, screenshot) = calculator.screenshot_raw().unwrap();
+        assert_eq!((width, height), (320, 240));
+        let Screenshot::Rgb(pixels) = screenshot else {
+            panic!("expected an RGB screenshot, got {screenshot:?}");
+        };
+        assert_eq!(pixels.len(), (width * height) as usize);
+        assert_eq!(pixels[0], 0xf800);
+    }
+
+    #[test]
+    fn monochrome_screenshot() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84Plus);
+        mock.reply(vec![mock::parameter_response(vec![
+            Parameter::ScreenWidth(96),
+            Parameter::ScreenHeight(64),
+            Parameter::ScreenContents(Screenshot::Raw(vec![0xff; 768])),
+        ])]);
+
+        let (width, height, screenshot) = calculator.screenshot_raw().unwrap();
+        assert_eq!((width, height), (96, 64));
+        assert!(matches!(screenshot, Screenshot::Monochrome(_)));
+    }
+}
@@ -0,0 +1,367 @@
+//! A fake calculator to test [`Calculator`] against, standing in for the USB device.
+//!
+//! It answers buffer size requests and acknowledges raw packets on its own. Everything else it
+//! sends has to be queued with [`Mock::reply`], one reply for each virtual packet it receives.
+
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    dusb::{Parameter, ParameterKind, VariableAttribute, VariableName, RGB_SCREEN_LENGTH},
+    model::Model,
+    packet::vtl::{DeviceError, VirtualPacketKind},
+    transfer::{CalculatorReset, Transport, UsbInterface},
+    Calculator, CalculatorBuilder, SAFE_PACKET_SIZE,
+};
+
+/// The timeout calculators connected with [`connect`] use, short so tests that wait it out
+/// don't take long.
+pub const TIMEOUT: Duration = Duration::from_millis(50);
+
+pub const INTERFACE: UsbInterface = UsbInterface {
+    number: 0,
+    read_endpoint: 0x81,
+    write_endpoint: 0x02,
+    max_packet_size: 64,
+};
+
+/// A calculator that answers the way [`Mock::new`] describes, connected with the default
+/// settings apart from [`TIMEOUT`].
+pub fn connect(model: Model) -> (Calculator, Mock) {
+    let mock = Mock::new();
+    let calculator = mock.connect(Calculator::builder(model).timeout(TIMEOUT));
+    (calculator, mock)
+}
+
+#[derive(Clone)]
+pub struct Mock(Arc<Mutex<State>>);
+
+struct State {
+    /// What the calculator has sent that hasn't been read yet.
+    pending: VecDeque<u8>,
+    /// What to answer each virtual packet with, as whole virtual packets.
+    replies: VecDeque<Vec<Vec<u8>>>,
+    /// The virtual packet being received, until its last raw packet arrives.
+    partial: Vec<u8>,
+    received: Vec<(u16, Vec<u8>)>,
+    /// Every write, each one a whole raw packet.
+    writes: Vec<Vec<u8>>,
+    /// The timeout each write was given.
+    write_timeouts: Vec<Duration>,
+    /// The largest buffer size reported, whatever is asked for.
+    allocation: u32,
+    /// Raw packets with more data than this are dropped without an acknowledgment.
+    max_working: u32,
+    /// The size of the raw packets replies are split into.
+    packet_size: u32,
+    /// How many more raw packets to drop, as if the calculator were asleep.
+    ignored: usize,
+    read_limit: Option<usize>,
+    read_delay: Duration,
+    /// How many bytes each bulk read asked for.
+    read_lengths: Vec<usize>,
+    connected: bool,
+    reappears: bool,
+}
+
+impl Mock {
+    /// A calculator that allocates whatever buffer size it's asked for and handles packets of
+    /// any size.
+    pub fn new() -> Self {
+        Self(Arc::new(Mutex::new(State {
+            pending: VecDeque::new(),
+            replies: VecDeque::new(),
+            partial: Vec::new(),
+            received: Vec::new(),
+            writes: Vec::new(),
+            write_timeouts: Vec::new(),
+            allocation: u32::MAX,
+            max_working: u32::MAX,
+            packet_size: SAFE_PACKET_SIZE,
+            ignored: 0,
+            read_limit: None,
+            read_delay: Duration::ZERO,
+            read_lengths: Vec::new(),
+            connected: true,
+            reappears: false,
+        })))
+    }
+
+    pub fn connect(&self, builder: CalculatorBuilder) -> Calculator {
+        let calculator = builder
+            .build_with_transport(Box::new(self.clone()), INTERFACE)
+            .unwrap();
+        let mut state = self.state();
+        state.received.clear();
+        state.writes.clear();
+        state.write_timeouts.clear();
+        drop(state);
+        calculator
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap()
+    }
+
+    /// Answers the next virtual packet received with `packets`, in order. Packets with no reply
+    /// queued get no answer beyond their acknowledgment.
+    pub fn reply(&self, packets: Vec<Vec<u8>>) {
+        self.state().replies.push_back(packets);
+    }
+
+    /// Sends bytes straight away, without waiting for anything to answer.
+    pub fn send_raw(&self, bytes: &[u8]) {
+        self.state().pending.extend(bytes);
+    }
+
+    /// The virtual packets received, as their kind and payload.
+    pub fn received(&self) -> Vec<(u16, Vec<u8>)> {
+        self.state().received.clone()
+    }
+
+    /// Every write, each one a whole raw packet.
+    pub fn writes(&self) -> Vec<Vec<u8>> {
+        self.state().writes.clone()
+    }
+
+    pub fn write_timeouts(&self) -> Vec<Duration> {
+        self.state().write_timeouts.clone()
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.state().pending.len()
+    }
+
+    pub fn read_lengths(&self) -> Vec<usize> {
+        self.state().read_lengths.clone()
+    }
+
+    pub fn set_allocation(&self, allocation: u32) {
+        self.state().allocation = allocation;
+    }
+
+    pub fn set_max_working(&self, max_working: u32) {
+        self.state().max_working = max_working;
+    }
+
+    /// Drops the next `count` raw packets without answering them.
+    pub fn ignore(&self, count: usize) {
+        self.state().ignored = count;
+    }
+
+    /// Hands out at most `limit` bytes per read.
+    pub fn set_read_limit(&self, limit: usize) {
+        self.state().read_limit = Some(limit);
+    }
+
+    /// Waits this long before each read that has something to return.
+    pub fn set_read_delay(&self, delay: Duration) {
+        self.state().read_delay = delay;
+    }
+
+    /// Disconnects the calculator. It comes back when reconnecting only if `reappears` is set.
+    pub fn unplug(&self, reappears: bool) {
+        let mut state = self.state();
+        state.connected = false;
+        state.reappears = reappears;
+    }
+}
+
+impl State {
+    fn receive_raw_packet(&mut self, bytes: &[u8]) {
+        if bytes.len() < 5 {
+            return;
+        }
+        let (kind, payload) = (bytes[4], &bytes[5..]);
+        if self.ignored > 0 {
+            self.ignored -= 1;
+            return;
+        }
+
+        match kind {
+            // Buffer size request
+            1 => {
+                let requested = u32::from_be_bytes(payload[..4].try_into().unwrap());
+                let size = requested.min(self.allocation);
+                self.packet_size = size.min(SAFE_PACKET_SIZE);
+                self.pending.extend(raw_packet(2, &size.to_be_bytes()));
+            }
+            // Virtual data
+            3 | 4 if payload.len() <= self.max_working as usize => {
+                self.pending.extend(raw_packet(5, &[0xe0, 0x00]));
+                self.partial.extend_from_slice(payload);
+                if kind == 4 {
+                    let packet = std::mem::take(&mut self.partial);
+                    let id = u16::from_be_bytes([packet[4], packet[5]]);
+                    self.received.push((id, packet[6..].to_vec()));
+
+                    for reply in self.replies.pop_front().unwrap_or_default() {
+                        let mut chunks = reply.chunks(self.packet_size as usize).peekable();
+                        while let Some(chunk) = chunks.next() {
+                            let kind = if chunks.peek().is_none() { 4 } else { 3 };
+                            self.pending.extend(raw_packet(kind, chunk));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Transport for Mock {
+    fn read_bulk(
+        &mut self,
+        _endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        let delay = {
+            let mut state = self.state();
+            state.read_lengths.push(buf.len());
+            if !state.connected {
+                return Err(rusb::Error::NoDevice);
+            }
+            if state.pending.is_empty() {
+                None
+            } else {
+                Some(state.read_delay)
+            }
+        };
+        let Some(delay) = delay else {
+            thread::sleep(timeout);
+            return Err(rusb::Error::Timeout);
+        };
+        thread::sleep(delay);
+
+        let mut state = self.state();
+        let length = buf
+            .len()
+            .min(state.pending.len())
+            .min(state.read_limit.unwrap_or(usize::MAX));
+        for (byte, pending) in buf.iter_mut().zip(state.pending.drain(..length)) {
+            *byte = pending;
+        }
+        Ok(length)
+    }
+
+    fn write_bulk(&mut self, _endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        let mut state = self.state();
+        if !state.connected {
+            return Err(rusb::Error::NoDevice);
+        }
+        state.writes.push(buf.to_vec());
+        state.write_timeouts.push(timeout);
+        state.receive_raw_packet(buf);
+
+        Ok(buf.len())
+    }
+
+    fn is_connected(&self) -> bool {
+        self.state().connected
+    }
+
+    fn reconnect(&mut self, _model: Model, _interface: u8) -> anyhow::Result<()> {
+        let mut state = self.state();
+        if !state.reappears {
+            return Err(CalculatorReset.into());
+        }
+        state.connected = true;
+        state.pending.clear();
+        Ok(())
+    }
+}
+
+/// A writer that can still be read after it's been handed off, like to
+/// [`Calculator::debug_output`].
+#[derive(Clone, Default)]
+pub struct SharedBuffer(pub Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn raw_packet(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.push(kind);
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// A whole virtual packet, before it's split into raw packets.
+pub fn virtual_packet(kind: VirtualPacketKind, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+    bytes.extend_from_slice(&(kind as u16).to_be_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+pub fn data_acknowledge() -> Vec<u8> {
+    virtual_packet(VirtualPacketKind::DataAcknowledge, &[])
+}
+
+pub fn end_of_transmission() -> Vec<u8> {
+    virtual_packet(VirtualPacketKind::EndOfTransmission, &[])
+}
+
+pub fn error(error: DeviceError) -> Vec<u8> {
+    virtual_packet(VirtualPacketKind::Error, &(error as u16).to_be_bytes())
+}
+
+pub fn variable_header(name: &str, attributes: Vec<VariableAttribute>) -> Vec<u8> {
+    let name = VariableName::from(name);
+    let mut payload = (name.len() as u16).to_be_bytes().to_vec();
+    payload.extend_from_slice(name.as_bytes());
+    payload.push(0);
+    payload.extend_from_slice(&(attributes.len() as u16).to_be_bytes());
+    for attribute in attributes {
+        payload.extend_from_slice(&attribute.id().to_be_bytes());
+        payload.push(0);
+        let data = attribute.into_payload();
+        payload.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        payload.extend_from_slice(&data);
+    }
+
+    virtual_packet(VirtualPacketKind::VariableHeader, &payload)
+}
+
+pub fn variable_contents(payload: &[u8]) -> Vec<u8> {
+    virtual_packet(VirtualPacketKind::VariableContents, payload)
+}
+
+pub fn parameter_response(parameters: Vec<Parameter>) -> Vec<u8> {
+    let mut payload = (parameters.len() as u16).to_be_bytes().to_vec();
+    for parameter in parameters {
+        payload.extend_from_slice(&(ParameterKind::from(&parameter) as u16).to_be_bytes());
+        payload.push(0);
+        let data = parameter.into_payload();
+        // Too long for the length field, which the calculator leaves as 0
+        let length = if data.len() == RGB_SCREEN_LENGTH {
+            0
+        } else {
+            data.len() as u16
+        };
+        payload.extend_from_slice(&length.to_be_bytes());
+        payload.extend_from_slice(&data);
+    }
+
+    virtual_packet(VirtualPacketKind::ParameterResponse, &payload)
+}
@@ -0,0 +1,215 @@
+use thiserror::Error;
+
+use crate::dusb::VariableKind;
+
+/// A calculator model, as far as it can be told apart over USB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// Any calculator connected through a SilverLink cable. The cable can't tell which
+    /// calculator is on the other end, so this assumes a TI-83.
+    Ti83,
+    Ti84Plus,
+    /// Also used for the 84+ SE and 84+ CSE, which share the same product ID.
+    Ti84PlusCe,
+    Nspire,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The older packet format used by link cables.
+    Dbus,
+    /// The raw/virtual packet format used by calculators with a built-in USB port.
+    Dusb,
+    /// The Nspire's own protocol, which isn't supported.
+    NavNet,
+}
+
+impl Model {
+    pub fn from_product_id(product_id: u16) -> Option<Self> {
+        Some(match product_id {
+            0xe001 => Self::Ti83,
+            0xe003 => Self::Ti84Plus,
+            0xe008 => Self::Ti84PlusCe,
+            0xe012 => Self::Nspire,
+            _ => return None,
+        })
+    }
+
+    /// A name for the model like the calculator's product string, for when it doesn't have one.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Ti83 => "TI-83",
+            Self::Ti84Plus => "TI-84 Plus",
+            Self::Ti84PlusCe => "TI-84 Plus CE",
+            Self::Nspire => "TI-Nspire",
+        }
+    }
+
+    /// Guesses the model from the USB product string, e.g. "TI-84 Plus CE". Returns `None` for
+    /// anything unrecognized, including link cables, which don't name the calculator.
+    pub fn from_product_string(product: &str) -> Option<Self> {
+        let product = product.trim().to_ascii_lowercase();
+        if product.contains("nspire") {
+            Some(Self::Nspire)
+        } else if product.contains(" ce") || product.contains("silver edition") {
+            Some(Self::Ti84PlusCe)
+        } else if product.starts_with("ti-84 plus") {
+            Some(Self::Ti84Plus)
+        } else {
+            None
+        }
+    }
+
+    /// The screen's width, height and bits per pixel. The 84+ SE shares a product ID with the
+    /// CE and the original Nspire with the CX, so for those this is the color model's screen.
+    pub fn screen_dimensions(self) -> (u32, u32, u8) {
+        match self {
+            Self::Ti83 | Self::Ti84Plus => (96, 64, 1),
+            Self::Ti84PlusCe | Self::Nspire => (320, 240, 16),
+        }
+    }
+
+    /// How many bytes each of the app pages counted by the app page parameters holds. The
+    /// 84+ family's Flash is split into 16KB pages, and the CE still counts space in 16KB
+    /// pages even though its Flash isn't paged. The 83 has no Flash and the Nspire doesn't
+    /// report pages, so those are `None`.
+    pub fn app_page_size(self) -> Option<u64> {
+        match self {
+            Self::Ti84Plus | Self::Ti84PlusCe => Some(16 * 1024),
+            Self::Ti83 | Self::Nspire => None,
+        }
+    }
+
+    /// How many bytes `pages` app pages hold, or `None` if the page size isn't known.
+    pub fn app_pages_to_bytes(self, pages: u64) -> Option<u64> {
+        self.app_page_size().map(|page_size| pages * page_size)
+    }
+
+    pub fn protocol(self) -> Protocol {
+        match self {
+            Self::Ti83 => Protocol::Dbus,
+            Self::Ti84Plus | Self::Ti84PlusCe => Protocol::Dusb,
+            Self::Nspire => Protocol::NavNet,
+        }
+    }
+
+    /// The kinds of variables this model can store. The Nspire stores documents instead of
+    /// the 83+/84+ variable types, so it doesn't support any of them.
+    pub fn supported_kinds(self) -> &'static [VariableKind] {
+        match self {
+            Self::Ti83 => &[
+                VariableKind::Equation,
+                VariableKind::String,
+                VariableKind::Program,
+                VariableKind::ProtectedProgram,
+                VariableKind::Picture,
+            ],
+            Self::Ti84Plus => &[
+                VariableKind::Equation,
+                VariableKind::String,
+                VariableKind::Program,
+                VariableKind::ProtectedProgram,
+                VariableKind::Picture,
+                VariableKind::AppVar,
+                VariableKind::App,
+            ],
+            Self::Ti84PlusCe => &[
+                VariableKind::Image,
+                VariableKind::Equation,
+                VariableKind::String,
+                VariableKind::Program,
+                VariableKind::ProtectedProgram,
+                VariableKind::Picture,
+                VariableKind::AppVar,
+                VariableKind::App,
+            ],
+            Self::Nspire => &[],
+        }
+    }
+
+    /// Checks that this model can store `kind`. [`VariableKind::Unknown`] is always allowed,
+    /// since there's no telling what it is and the calculator gets the final say.
+    pub fn check_supported(self, kind: VariableKind) -> Result<(), KindNotSupported> {
+        if kind == VariableKind::Unknown || self.supported_kinds().contains(&kind) {
+            Ok(())
+        } else {
+            Err(KindNotSupported { model: self, kind })
+        }
+    }
+
+    /// Whether the calculator has an ID list to read with
+    /// [`Calculator::get_id_list`](crate::Calculator::get_id_list).
+    pub fn has_id_list(self) -> bool {
+        matches!(self, Self::Ti84Plus | Self::Ti84PlusCe)
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("{model:?} uses the {protocol:?} protocol, which isn't supported here")]
+pub struct UnsupportedProtocol {
+    pub model: Model,
+    pub protocol: Protocol,
+}
+
+#[derive(Debug, Error)]
+#[error("{0:?} has no ID list")]
+pub struct NoIdList(pub Model);
+
+#[derive(Debug, Error)]
+#[error("{model:?} doesn't support {kind:?} variables")]
+pub struct KindNotSupported {
+    pub model: Model,
+    pub kind: VariableKind,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn product_strings() {
+        let expected = [
+            ("TI-84 Plus", Some(Model::Ti84Plus)),
+            ("TI-84 Plus CE", Some(Model::Ti84PlusCe)),
+            ("TI-84 Plus C Silver Edition", Some(Model::Ti84PlusCe)),
+            ("  ti-84 plus ce-t ", Some(Model::Ti84PlusCe)),
+            ("TI-Nspire CX", Some(Model::Nspire)),
+            ("TI-GRAPH LINK USB", None),
+            ("", None),
+        ];
+        for (product, model) in expected {
+            assert_eq!(Model::from_product_string(product), model, "{product:?}");
+        }
+    }
+
+    #[test]
+    fn screens() {
+        assert_eq!(Model::Ti84Plus.screen_dimensions(), (96, 64, 1));
+        assert_eq!(Model::Ti84PlusCe.screen_dimensions(), (320, 240, 16));
+    }
+
+    #[test]
+    fn app_pages_to_bytes() {
+        assert_eq!(Model::Ti84PlusCe.app_pages_to_bytes(3), Some(49152));
+        assert_eq!(Model::Ti84Plus.app_pages_to_bytes(94), Some(1_540_096));
+        assert_eq!(Model::Ti84Plus.app_pages_to_bytes(0), Some(0));
+        assert_eq!(Model::Nspire.app_pages_to_bytes(3), None);
+    }
+
+    #[test]
+    fn supported_kinds() {
+        assert!(Model::Ti84PlusCe
+            .check_supported(VariableKind::Image)
+            .is_ok());
+        let err = Model::Ti84Plus
+            .check_supported(VariableKind::Image)
+            .unwrap_err();
+        assert_eq!(
+            (err.model, err.kind),
+            (Model::Ti84Plus, VariableKind::Image)
+        );
+        assert!(Model::Ti84Plus
+            .check_supported(VariableKind::Unknown)
+            .is_ok());
+    }
+}
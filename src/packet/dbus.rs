@@ -0,0 +1,183 @@
+use core::fmt;
+use std::io::Read;
+
+use byteorder::{ReadBytesExt, LE};
+use strum::FromRepr;
+use thiserror::Error;
+
+/// Sent by the computer to a TI-83 (the calculator replies with `0x83`).
+pub const PC_TI83: u8 = 0x03;
+
+/// A packet of the older link protocol used through SilverLink and black link cables, which
+/// has no raw/virtual layering. Every packet is a 4 byte header (machine ID, command and a
+/// little-endian length), followed by the data and its checksum if the command carries any.
+#[derive(Debug, Clone)]
+pub struct DbusPacket {
+    pub machine_id: u8,
+    pub command: DbusCommand,
+    pub data: Vec<u8>,
+}
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromRepr)]
+pub enum DbusCommand {
+    VariableHeader = 0x06,
+    ClearToSend = 0x09,
+    Data = 0x15,
+    Version = 0x2d,
+    Skip = 0x36,
+    Acknowledge = 0x56,
+    ChecksumError = 0x5a,
+    Ready = 0x68,
+    Screenshot = 0x6d,
+    RemoteId = 0x74,
+    Continue = 0x78,
+    Key = 0x87,
+    Delete = 0x88,
+    EndOfTransmission = 0x92,
+    Request = 0xa2,
+    RequestToSend = 0xc9,
+}
+
+impl DbusCommand {
+    /// Whether packets with this command are followed by data and a checksum. For the others
+    /// the length field is meaningless (and not always zero).
+    pub fn has_data(self) -> bool {
+        !matches!(
+            self,
+            Self::ClearToSend
+                | Self::Acknowledge
+                | Self::ChecksumError
+                | Self::Ready
+                | Self::Screenshot
+                | Self::RemoteId
+                | Self::Key
+                | Self::EndOfTransmission
+                | Self::Continue
+        )
+    }
+}
+
+impl DbusPacket {
+    pub fn new(machine_id: u8, command: DbusCommand, data: Vec<u8>) -> Self {
+        Self {
+            machine_id,
+            command,
+            data,
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![self.machine_id, self.command as u8];
+        bytes.extend_from_slice(&(self.data.len() as u16).to_le_bytes());
+        if self.command.has_data() {
+            bytes.extend_from_slice(&self.data);
+            bytes.extend_from_slice(&checksum(&self.data).to_le_bytes());
+        }
+
+        bytes
+    }
+
+    pub fn receive<R: Read>(reader: &mut R) -> anyhow::Result<Self> {
+        let machine_id = reader.read_u8()?;
+        let command = reader.read_u8()?;
+        let length = reader.read_u16::<LE>()?;
+
+        let command = DbusCommand::from_repr(command).ok_or(UnknownCommandError(command))?;
+        println!("TI->PC: Received legacy packet {command:?}");
+
+        let mut data = Vec::new();
+        if command.has_data() {
+            data.resize(length as usize, 0);
+            reader.read_exact(&mut data)?;
+
+            let expected = reader.read_u16::<LE>()?;
+            let received = checksum(&data);
+            if expected != received {
+                return Err(ChecksumMismatch { expected, received }.into());
+            }
+        }
+
+        Ok(Self {
+            machine_id,
+            command,
+            data,
+        })
+    }
+
+    pub fn expect(self, command: DbusCommand) -> Result<Self, WrongCommand> {
+        if self.command == command {
+            Ok(self)
+        } else {
+            Err(WrongCommand {
+                expected: command,
+                received: self.command,
+            })
+        }
+    }
+}
+
+pub fn checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16))
+}
+
+#[derive(Error, Debug)]
+#[error("wrong packet command: expected {expected:?}, received {received:?}")]
+pub struct WrongCommand {
+    pub expected: DbusCommand,
+    pub received: DbusCommand,
+}
+
+#[derive(Error, Debug)]
+#[error("checksum mismatch: expected {expected:#06x}, calculated {received:#06x}")]
+pub struct ChecksumMismatch {
+    pub expected: u16,
+    pub received: u16,
+}
+
+#[derive(Error, Debug)]
+pub struct UnknownCommandError(pub u8);
+impl fmt::Display for UnknownCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown legacy packet command {:#04x}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let bytes =
+            DbusPacket::new(PC_TI83, DbusCommand::Data, vec![0x12, 0x34, 0xff]).into_bytes();
+        assert_eq!(bytes, [0x03, 0x15, 3, 0, 0x12, 0x34, 0xff, 0x45, 0x01]);
+
+        let packet = DbusPacket::receive(&mut bytes.as_slice()).unwrap();
+        assert_eq!(packet.machine_id, PC_TI83);
+        assert_eq!(packet.command, DbusCommand::Data);
+        assert_eq!(packet.data, [0x12, 0x34, 0xff]);
+    }
+
+    #[test]
+    fn commands_without_data_ignore_length() {
+        let mut bytes: &[u8] = &[0x83, 0x56, 0x1b, 0x01];
+        let packet = DbusPacket::receive(&mut bytes).unwrap();
+
+        assert_eq!(packet.command, DbusCommand::Acknowledge);
+        assert!(packet.data.is_empty());
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn corrupted_data_fails_checksum() {
+        let mut bytes = DbusPacket::new(PC_TI83, DbusCommand::Data, vec![1, 2, 3]).into_bytes();
+        bytes[5] = 4;
+
+        let err = DbusPacket::receive(&mut bytes.as_slice()).unwrap_err();
+        let mismatch = err.downcast_ref::<ChecksumMismatch>().unwrap();
+        assert_eq!(mismatch.expected, 6);
+        assert_eq!(mismatch.received, 8);
+    }
+}
@@ -1,5 +1,5 @@
 use core::fmt;
-use std::io::Read;
+use std::io::{self, Read};
 
 use thiserror::Error;
 
@@ -8,6 +8,9 @@ use crate::{
     Calculator,
 };
 
+/// The size and kind in front of every raw packet's payload.
+pub const RAW_HEADER_LENGTH: usize = 5;
+
 #[repr(u8)]
 #[derive(Debug)]
 pub enum RawPacket {
@@ -20,22 +23,48 @@ pub enum RawPacket {
 
 impl RawPacket {
     pub fn receive(handle: &mut Calculator) -> anyhow::Result<Self> {
-        let mut size_buf = [0; 4];
-        let mut kind_buf = [0; 1];
-        handle.read_exact(&mut size_buf)?;
-        handle.read_exact(&mut kind_buf)?;
+        let max_size = handle.max_raw_packet_size;
+        Self::read_from(handle, max_size)
+    }
 
-        let size = u32::from_be_bytes(size_buf);
-        let kind = kind_buf[0];
+    /// Reads the next raw packet of a known kind from `reader`, skipping any others. A packet
+    /// bigger than `max_size` fails with [`WrongPacketSize`] before anything is allocated for
+    /// it.
+    pub fn read_from<R: Read>(reader: &mut R, max_size: u32) -> anyhow::Result<Self> {
+        loop {
+            let mut size_buf = [0; 4];
+            let mut kind_buf = [0; 1];
+            reader.read_exact(&mut size_buf)?;
+            reader.read_exact(&mut kind_buf)?;
 
-        let mut payload = vec![0; size as usize];
-        handle.read_exact(&mut payload)?;
+            let size = u32::from_be_bytes(size_buf);
+            let kind = match RawPacketKind::try_from(kind_buf[0]) {
+                Ok(kind) => kind,
+                Err(err) => {
+                    // Skipping the whole payload means nothing is left behind to desync the
+                    // connection
+                    println!("TI->PC: Ignoring packet with {err} ({size} bytes)");
+                    let skipped = io::copy(&mut reader.take(size as u64), &mut io::sink())?;
+                    if skipped < size as u64 {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                    }
+                    continue;
+                }
+            };
+            if size > max_size {
+                return Err(WrongPacketSize {
+                    expected: max_size,
+                    received: size,
+                }
+                .into());
+            }
 
-        if let Ok(kind) = RawPacketKind::try_from(kind) {
+            let mut payload = vec![0; size as usize];
+            reader.read_exact(&mut payload)?;
             println!("TI->PC: Received raw packet {kind:?}",);
-        }
 
-        Ok(Self::from_payload(kind, payload)?)
+            return Self::from_payload(kind as u8, payload);
+        }
     }
 
     pub fn receive_exact(kind: RawPacketKind, handle: &mut Calculator) -> anyhow::Result<Self> {
@@ -51,14 +80,18 @@ impl RawPacket {
         }
     }
 
-    pub fn from_payload(kind: u8, payload: Vec<u8>) -> Result<Self, UnknownPacketKindError> {
+    /// Decodes a raw packet's payload. A payload too short for its kind fails with
+    /// [`InvalidPayload`] instead of panicking.
+    pub fn from_payload(kind: u8, payload: Vec<u8>) -> anyhow::Result<Self> {
         Ok(match kind {
-            1 => Self::RequestBufSize(u32_from_bytes(&payload[0..4])),
-            2 => Self::RespondBufSize(u32_from_bytes(&payload[0..4])),
+            1 => Self::RequestBufSize(u32_from_bytes(payload.get(..4).ok_or(InvalidPayload)?)),
+            2 => Self::RespondBufSize(u32_from_bytes(payload.get(..4).ok_or(InvalidPayload)?)),
             3 => Self::VirtualData(payload),
             4 => Self::FinalVirtData(payload),
-            5 => Self::VirtualDataAcknowledge(u16_from_bytes(&payload[0..2])),
-            x => return Err(UnknownPacketKindError(x)),
+            5 => Self::VirtualDataAcknowledge(u16_from_bytes(
+                payload.get(..2).ok_or(InvalidPayload)?,
+            )),
+            x => return Err(UnknownPacketKindError(x).into()),
         })
     }
 
@@ -72,11 +105,19 @@ impl RawPacket {
         }
     }
 
-    pub fn send(self, handle: &Calculator) -> anyhow::Result<()> {
+    pub fn send(self, handle: &mut Calculator) -> anyhow::Result<()> {
         let kind = self.kind();
         let id = kind as u8;
 
         let payload = self.into_payload();
+        // Checked here so a bad packet fails clearly instead of being rejected by the calculator
+        if payload.len() > handle.max_raw_packet_size as usize {
+            return Err(WrongPacketSize {
+                expected: handle.max_raw_packet_size,
+                received: payload.len() as u32,
+            }
+            .into());
+        }
 
         let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
         bytes.push(id);
@@ -150,3 +191,81 @@ impl TryFrom<u8> for RawPacketKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+    use crate::{mock, model::Model};
+
+    #[test]
+    fn short_payloads_are_errors() {
+        for (kind, length) in [(1, 4), (2, 4), (5, 2)] {
+            for short in 0..length {
+                let result = RawPacket::from_payload(kind, vec![0; short]);
+                assert!(
+                    result.is_err_and(|err| err.is::<InvalidPayload>()),
+                    "kind {kind} with {short} bytes"
+                );
+            }
+            assert!(RawPacket::from_payload(kind, vec![0; length]).is_ok());
+        }
+    }
+
+    #[test]
+    fn unknown_kind_is_error() {
+        let result = RawPacket::from_payload(6, vec![0; 4]);
+        assert!(result.is_err_and(|err| err.is::<UnknownPacketKindError>()));
+    }
+
+    #[test]
+    fn unknown_packets_are_skipped_whole() {
+        let mut bytes: &[u8] = &[0, 0, 0, 3, 9, 1, 2, 3, 0, 0, 0, 2, 5, 0xe0, 0x00];
+        let packet = RawPacket::read_from(&mut bytes, 1018).unwrap();
+
+        assert!(matches!(packet, RawPacket::VirtualDataAcknowledge(0xe000)));
+        assert!(bytes.is_empty());
+    }
+
+    #[test]
+    fn oversized_packet_is_error() {
+        let mut bytes: &[u8] = &[0xff, 0xff, 0xff, 0xff, 3];
+        let result = RawPacket::read_from(&mut bytes, 1018);
+        assert!(result.is_err_and(|err| err.is::<WrongPacketSize>()));
+    }
+
+    #[test]
+    fn oversized_packet_is_not_sent() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let payload = vec![0; calculator.max_raw_packet_size as usize + 1];
+
+        let result = RawPacket::FinalVirtData(payload).send(&mut calculator);
+
+        assert!(result.is_err_and(|err| err.is::<WrongPacketSize>()));
+        assert!(mock.writes().is_empty());
+    }
+
+    proptest! {
+        /// Whatever the calculator sends, reading never panics and never allocates more than
+        /// `max_size` for a payload, however big the size it claims.
+        #[test]
+        fn random_bytes_never_panic(
+            size in prop_oneof![any::<u32>(), 0..64u32],
+            kind in 0..8u8,
+            rest in vec(any::<u8>(), 0..256),
+            max_size in 0..128u32,
+        ) {
+            let mut bytes = size.to_be_bytes().to_vec();
+            bytes.push(kind);
+            bytes.extend_from_slice(&rest);
+
+            let mut reader = bytes.as_slice();
+            if let Ok(packet) = RawPacket::read_from(&mut reader, max_size) {
+                let payload = packet.into_payload();
+                prop_assert!(payload.len() <= max_size as usize);
+                prop_assert!(payload.len() <= rest.len());
+            }
+        }
+    }
+}
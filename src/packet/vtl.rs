@@ -1,5 +1,8 @@
-use core::fmt;
-use std::io::{Cursor, Read};
+use std::{
+    io::{Cursor, Read},
+    thread,
+    time::{Duration, Instant},
+};
 
 use byteorder::{BigEndian, ReadBytesExt, BE};
 use strum::{EnumDiscriminants, FromRepr};
@@ -7,33 +10,52 @@ use thiserror::Error;
 
 use crate::{
     dusb::{
-        Mode, Parameter, ParameterKind, UnknownParameterKindError, Variable, VariableAttribute,
-        VariableAttributeKind, VariableContents,
+        ExecuteAction, Mode, Parameter, ParameterKind, ParameterSet, Unavailable, UnknownModeError,
+        UnknownParameterKindError, Variable, VariableAttribute, VariableAttributeKind,
+        VariableName, RGB_SCREEN_LENGTH,
     },
-    util::{u16_from_bytes, u32_from_bytes},
+    util::u32_from_bytes,
     Calculator,
 };
 
 use super::raw::{self, InvalidPayload, RawPacket, RawPacketKind};
 
+/// The most memory reserved up front for an incoming virtual packet.
+const MAX_PREALLOCATION: usize = 1024 * 1024;
+
 #[repr(u16)]
 #[derive(Debug, EnumDiscriminants)]
 #[strum_discriminants(name(VirtualPacketKind))]
 #[strum_discriminants(derive(FromRepr))]
 pub enum VirtualPacket {
     SetMode(Mode) = 0x0001,
+    OsBegin(Vec<u8>) = 0x0002,
+    OsAcknowledge(Vec<u8>) = 0x0003,
+    OsHeader(Vec<u8>) = 0x0004,
+    OsData(Vec<u8>) = 0x0005,
+    EndOfTransmissionAcknowledge = 0x0006,
     ParameterRequest(Vec<ParameterKind>) = 0x0007,
-    ParameterResponse(Vec<Parameter>) = 0x0008,
+    ParameterResponse(ParameterSet) = 0x0008,
     DirectoryRequest(Vec<VariableAttributeKind>) = 0x0009,
     VariableHeader(Variable) = 0x000a,
     RequestToSend(Variable) = 0x000b,
-    RequestVariable(String, Vec<VariableAttributeKind>, Vec<VariableAttribute>) = 0x000c,
+    RequestVariable(
+        VariableName,
+        Vec<VariableAttributeKind>,
+        Vec<VariableAttribute>,
+    ) = 0x000c,
     VariableContents(Vec<u8>) = 0x000d,
+    ParameterSet(Parameter) = 0x000e,
+    /// Renames or deletes a variable, or changes its attributes.
+    ModifyVariable(Vec<u8>) = 0x0010,
+    Execute(ExecuteAction) = 0x0011,
     SetModeAcknowledge = 0x0012,
     DataAcknowledge = 0xaa00,
     Wait(u32) = 0xbb00,
     EndOfTransmission = 0xdd00,
     Error(DeviceError) = 0xee00,
+    /// A packet kind we don't know about, with its ID and payload.
+    Unknown(u16, Vec<u8>) = 0xffff,
 }
 
 #[repr(u16)]
@@ -71,9 +93,13 @@ pub enum DeviceError {
     HandheldBusy = 0x34,
 }
 
-impl From<VirtualPacket> for Vec<u8> {
-    fn from(packet: VirtualPacket) -> Vec<u8> {
-        match packet {
+impl TryFrom<VirtualPacket> for Vec<u8> {
+    type Error = Unsendable;
+
+    /// Encodes the packet's payload. Fails for packets only the calculator sends, which can't
+    /// be encoded yet.
+    fn try_from(packet: VirtualPacket) -> Result<Vec<u8>, Unsendable> {
+        Ok(match packet {
             VirtualPacket::SetMode(mode) => mode.into(),
             VirtualPacket::ParameterRequest(parameters) => {
                 let mut payload = (parameters.len() as u16).to_be_bytes().to_vec();
@@ -85,6 +111,7 @@ impl From<VirtualPacket> for Vec<u8> {
                 payload
             }
             VirtualPacket::DirectoryRequest(attributes) => {
+                // Unlike every other attribute list, the count here really is 4 bytes
                 let mut payload = (attributes.len() as u32).to_be_bytes().to_vec();
 
                 for attribute in attributes {
@@ -109,9 +136,7 @@ impl From<VirtualPacket> for Vec<u8> {
 
                 payload.extend_from_slice(&(specified_attributes.len() as u16).to_be_bytes());
                 for attr in specified_attributes {
-                    payload.extend_from_slice(
-                        &(VariableAttributeKind::from(attr.clone()) as u16).to_be_bytes(),
-                    );
+                    payload.extend_from_slice(&attr.id().to_be_bytes());
                     let attr_payload = attr.into_payload();
                     payload.extend_from_slice(&(attr_payload.len() as u16).to_be_bytes());
                     payload.extend_from_slice(&attr_payload);
@@ -127,8 +152,7 @@ impl From<VirtualPacket> for Vec<u8> {
 
                 payload.extend_from_slice(&(variable.attributes.len() as u16).to_be_bytes());
                 for attribute in variable.attributes {
-                    let kind: VariableAttributeKind = (&attribute).into();
-                    payload.extend_from_slice(&(kind as u16).to_be_bytes());
+                    payload.extend_from_slice(&attribute.id().to_be_bytes());
                     let bytes = attribute.into_payload();
                     payload.extend_from_slice(&(bytes.len() as u16).to_be_bytes());
                     payload.extend_from_slice(&bytes);
@@ -137,19 +161,47 @@ impl From<VirtualPacket> for Vec<u8> {
                 payload
             }
             VirtualPacket::VariableContents(variable) => variable,
-            VirtualPacket::EndOfTransmission => Vec::new(),
-            packet => todo!("tried to send unhandled packet kind {packet:?}"),
-        }
+            VirtualPacket::ParameterSet(parameter) => {
+                let mut payload = (ParameterKind::from(&parameter) as u16)
+                    .to_be_bytes()
+                    .to_vec();
+                let data = parameter.into_payload();
+                payload.extend_from_slice(&(data.len() as u16).to_be_bytes());
+                payload.extend_from_slice(&data);
+                payload
+            }
+            VirtualPacket::Execute(action) => action.into(),
+            VirtualPacket::EndOfTransmission | VirtualPacket::EndOfTransmissionAcknowledge => {
+                Vec::new()
+            }
+            VirtualPacket::OsBegin(payload)
+            | VirtualPacket::OsAcknowledge(payload)
+            | VirtualPacket::OsHeader(payload)
+            | VirtualPacket::OsData(payload)
+            | VirtualPacket::ModifyVariable(payload)
+            | VirtualPacket::Unknown(_, payload) => payload,
+            packet => return Err(Unsendable(packet.into())),
+        })
     }
 }
 
 impl VirtualPacket {
-    pub fn into_raw_packets(self, max_size: u32) -> Vec<RawPacket> {
-        let kind = VirtualPacketKind::from(&self);
-        let contents: Vec<u8> = self.into();
+    pub fn id(&self) -> u16 {
+        match self {
+            Self::Unknown(id, _) => *id,
+            packet => VirtualPacketKind::from(packet) as u16,
+        }
+    }
+
+    /// Splits the packet into raw packets of at most `max_size` bytes, with the last one marked
+    /// as final. When the packet divides evenly the last full chunk is the final one; no empty
+    /// final packet follows it, matching what TI Connect and libticalcs send.
+    pub fn into_raw_packets(self, max_size: u32) -> Result<Vec<RawPacket>, Unsendable> {
+        let id = self.id();
+        let contents = Vec::try_from(self)?;
 
         let mut bytes = (contents.len() as u32).to_be_bytes().to_vec();
-        bytes.extend_from_slice(&(kind as u16).to_be_bytes());
+        bytes.extend_from_slice(&id.to_be_bytes());
         bytes.extend_from_slice(&contents);
 
         let mut packets = Vec::new();
@@ -163,7 +215,7 @@ impl VirtualPacket {
             });
         }
 
-        packets
+        Ok(packets)
     }
 
     pub fn send(self, handle: &mut Calculator) -> anyhow::Result<()> {
@@ -171,51 +223,42 @@ impl VirtualPacket {
             "PC->TI: Sending virtual packet {:?}",
             VirtualPacketKind::from(&self)
         );
-        let packets = self.into_raw_packets(handle.max_raw_packet_size);
+        let packets = self.into_raw_packets(handle.max_raw_packet_size)?;
+
+        // Each raw packet is acknowledged in order, so only the number still waiting on one
+        // needs to be tracked
+        let mut unacknowledged = 0;
         for packet in packets {
             packet.send(handle)?;
-            Self::wait_for_acknowledge(handle)?;
-        }
-
-        Ok(())
-    }
-
-    pub fn wait_for_acknowledge(handle: &mut Calculator) -> anyhow::Result<()> {
-        let packet = RawPacket::receive(handle)?;
-        match packet {
-            RawPacket::RequestBufSize(size) => {
-                println!("TI->PC: Buffer Size Request ({size} bytes)");
-                RawPacket::RespondBufSize(handle.max_raw_packet_size).send(handle)?;
+            unacknowledged += 1;
+            if unacknowledged >= handle.ack_window {
                 Self::wait_for_acknowledge(handle)?;
-            }
-            RawPacket::VirtualDataAcknowledge(contents) => {
-                // It should always have this, no one knows why
-                if contents != 0xe000 {
-                    return Err(InvalidPayload.into());
-                }
-            }
-            packet => {
-                return Err(raw::WrongPacketKind {
-                    expected: RawPacketKind::VirtDataAck,
-                    received: packet.kind(),
-                }
-                .into())
+                unacknowledged -= 1;
             }
         }
+        for _ in 0..unacknowledged {
+            Self::wait_for_acknowledge(handle)?;
+        }
 
         Ok(())
     }
 
-    fn receive_bytes(handle: &mut Calculator) -> anyhow::Result<Vec<u8>> {
-        let mut bytes = Vec::new();
-
+    /// Waits for the raw-level acknowledgment sent after each raw packet, answering any buffer
+    /// size requests that come first.
+    pub fn wait_for_acknowledge(handle: &mut Calculator) -> anyhow::Result<()> {
         loop {
             match RawPacket::receive(handle)? {
-                RawPacket::VirtualData(ref payload) => bytes.extend_from_slice(payload),
-                RawPacket::FinalVirtData(ref payload) => {
-                    bytes.extend_from_slice(payload);
-                    RawPacket::VirtualDataAcknowledge(0xe000).send(handle)?;
-                    return Ok(bytes);
+                RawPacket::RequestBufSize(size) => {
+                    println!("TI->PC: Buffer Size Request ({size} bytes)");
+                    RawPacket::RespondBufSize(handle.max_raw_packet_size).send(handle)?;
+                }
+                RawPacket::VirtualDataAcknowledge(contents) => {
+                    // As lenient as libticalcs, which only fails if neither byte is right
+                    check_acknowledgment(contents)?;
+                    if contents != 0xe000 {
+                        println!("TI->PC: Acknowledgment with unusual value {contents:#06x}");
+                    }
+                    return Ok(());
                 }
                 packet => {
                     return Err(raw::WrongPacketKind {
@@ -225,66 +268,135 @@ impl VirtualPacket {
                     .into())
                 }
             }
+        }
+    }
+
+    /// Waits for a virtual-level acknowledgment like `DataAcknowledge`, waiting once if the
+    /// calculator asks to. An `Error` packet is returned as the [`DeviceError`] it carries.
+    pub fn receive_acknowledge(
+        handle: &mut Calculator,
+        expected: VirtualPacketKind,
+    ) -> anyhow::Result<()> {
+        let operation = format!("waiting for {expected:?}");
+        let mut packet = Self::receive_during(handle, &operation)?;
+        if let Self::Wait(ms) = packet {
+            println!("Waiting {ms}ms...");
+            thread::sleep(Duration::from_millis(100));
+            packet = Self::receive_during(handle, &operation)?;
+        }
+
+        match packet {
+            packet if VirtualPacketKind::from(&packet) == expected => Ok(()),
+            packet => Err(WrongPacketKind::new(expected, packet).into()),
+        }
+    }
+
+    fn receive_bytes(handle: &mut Calculator) -> anyhow::Result<Vec<u8>> {
+        let mut reassembler = Reassembler::default();
+
+        // Each read has its own timeout, but a calculator that keeps sending just often enough
+        // to avoid it could otherwise keep this going forever
+        let deadline = Instant::now() + handle.packet_timeout;
+
+        loop {
+            if Instant::now() > deadline {
+                return Err(PacketTimedOut(handle.packet_timeout).into());
+            }
+
+            let last = reassembler.push(RawPacket::receive(handle)?)?;
 
+            // Every data packet, including the last, gets exactly one acknowledgment
             RawPacket::VirtualDataAcknowledge(0xe000).send(handle)?;
+            if last {
+                return Ok(reassembler.bytes);
+            }
         }
     }
 
     pub fn receive(handle: &mut Calculator) -> anyhow::Result<Self> {
         let bytes = Self::receive_bytes(handle)?;
-        let size = u32_from_bytes(&bytes[0..4]);
-        let kind = u16_from_bytes(&bytes[4..6]);
-        let payload = bytes[6..6 + size as usize].to_vec();
+        Self::try_from_bytes(&bytes)
+    }
 
-        let kind = VirtualPacketKind::from_repr(kind).ok_or(UnknownPacketKindError(kind))?;
+    /// Receives a packet for an exchange where the calculator can refuse what was asked. An
+    /// `Error` packet is returned as the [`DeviceError`] it carries, with `operation` (e.g.
+    /// "listing variables") as context. The error can still be downcast to the `DeviceError`.
+    pub fn receive_during(handle: &mut Calculator, operation: &str) -> anyhow::Result<Self> {
+        match Self::receive(handle)? {
+            Self::Error(err) => Err(anyhow::Error::new(err)
+                .context(format!("calculator reported an error while {operation}"))),
+            packet => Ok(packet),
+        }
+    }
+
+    /// Decodes a whole virtual packet, including its size and kind. Malformed input is
+    /// reported as an error instead of panicking.
+    pub fn try_from_bytes(mut bytes: &[u8]) -> anyhow::Result<Self> {
+        let size = bytes.read_u32::<BE>()? as usize;
+        let kind = bytes.read_u16::<BE>()?;
+        let payload = bytes.get(..size).ok_or(InvalidPayload)?;
+
+        let Some(kind) =
+            VirtualPacketKind::from_repr(kind).filter(|kind| *kind != VirtualPacketKind::Unknown)
+        else {
+            println!("TI->PC: Received unknown virtual packet {kind:#06x}");
+            return Ok(Self::Unknown(kind, payload.to_vec()));
+        };
         println!("TI->PC: Received virtual packet {kind:?}");
-        Self::from_payload(kind, &payload)
+        Self::from_payload(kind, payload)
     }
 
     pub fn from_payload(kind: VirtualPacketKind, mut payload: &[u8]) -> anyhow::Result<Self> {
         Ok(match kind {
-            VirtualPacketKind::SetMode => Self::SetMode(Mode::from(&payload[0..2])),
+            VirtualPacketKind::SetMode => {
+                let mode = payload.read_u16::<BE>()?;
+                let mode = u8::try_from(mode)
+                    .ok()
+                    .and_then(Mode::from_repr)
+                    .ok_or(UnknownModeError(mode))?;
+                Self::SetMode(mode)
+            }
             VirtualPacketKind::ParameterRequest => {
-                let amount = u16_from_bytes(&payload[0..2]) as usize;
-
-                let parameters = payload
-                    .chunks_exact(2)
-                    .skip(1)
-                    .take(amount)
-                    .map(|pair| {
-                        let id = u16_from_bytes(pair);
-                        ParameterKind::from_repr(id).unwrap()
+                let amount = payload.read_u16::<BE>()?;
+
+                let parameters = (0..amount)
+                    .map(|_| {
+                        let id = payload.read_u16::<BE>()?;
+                        Ok(ParameterKind::from_repr(id).ok_or(UnknownParameterKindError(id))?)
                     })
-                    .collect();
+                    .collect::<anyhow::Result<_>>()?;
                 Self::ParameterRequest(parameters)
             }
             VirtualPacketKind::ParameterResponse => {
-                let mut parameters = Vec::new();
+                let mut parameters = ParameterSet::default();
                 let mut payload_cursor = Cursor::new(payload);
                 let amount = payload_cursor.read_u16::<BigEndian>()? as usize;
                 for _ in 0..amount {
                     let id = payload_cursor.read_u16::<BigEndian>()?;
-                    let is_valid = payload_cursor.read_u8()? == 0;
-                    if !is_valid {
+                    let code = payload_cursor.read_u8()?;
+                    if code != 0 {
+                        parameters.unavailable.push(Unavailable { id, code });
                         continue;
                     }
 
                     let parameter_length = {
                         // if the parameter is bigger than u16::MAX, the calc will set the length to 0
                         // stupid dum hack because screenshots on some devices are huge
-                        let length = payload_cursor.read_u16::<BigEndian>()? as u32;
-                        if length == 0 {
-                            153600
+                        let length = payload_cursor.read_u16::<BigEndian>()? as usize;
+                        if length == 0 && id == ParameterKind::ScreenContents as u16 {
+                            RGB_SCREEN_LENGTH
                         } else {
                             length
                         }
                     };
 
-                    let mut parameter_data = vec![0; parameter_length as usize];
+                    let mut parameter_data = vec![0; parameter_length];
                     payload_cursor.read_exact(&mut parameter_data)?;
 
                     let kind = ParameterKind::from_repr(id).ok_or(UnknownParameterKindError(id))?;
-                    parameters.push(Parameter::from_payload(kind, &parameter_data)?);
+                    parameters
+                        .parameters
+                        .push(Parameter::from_payload(kind, &parameter_data)?);
                 }
 
                 Self::ParameterResponse(parameters)
@@ -296,51 +408,120 @@ impl VirtualPacket {
                 payload.read_exact(&mut name_bytes)?;
                 payload.read_u8()?; // 0x00
                 let attribute_count = payload.read_u16::<BE>()?;
-                let name = String::from_utf8_lossy(&name_bytes).into_owned();
+                let name = VariableName(name_bytes);
 
                 let mut attributes = Vec::new();
+                let mut unavailable = Vec::new();
                 for _ in 0..attribute_count {
                     let id = payload.read_u16::<BE>()?;
-                    let is_valid = payload.read_u8()? == 0;
+                    let code = payload.read_u8()?;
 
-                    if is_valid {
+                    if code != 0 {
+                        unavailable.push(Unavailable { id, code });
+                    } else {
                         let data_length = payload.read_u16::<BE>()?;
 
                         let mut attribute_data = vec![0; data_length as usize];
                         payload.read_exact(&mut attribute_data)?;
 
-                        attributes.push(VariableAttribute::from_payload(
-                            VariableAttributeKind::from_repr(id).unwrap(),
-                            &attribute_data,
-                        )?);
+                        attributes.push(VariableAttribute::from_id(id, &attribute_data)?);
                     }
                 }
 
-                Self::VariableHeader(Variable { name, attributes })
+                Self::VariableHeader(Variable {
+                    name,
+                    attributes,
+                    unavailable,
+                })
             }
             VirtualPacketKind::VariableContents => Self::VariableContents(payload.to_vec()),
+            VirtualPacketKind::OsBegin => Self::OsBegin(payload.to_vec()),
+            VirtualPacketKind::OsAcknowledge => Self::OsAcknowledge(payload.to_vec()),
+            VirtualPacketKind::OsHeader => Self::OsHeader(payload.to_vec()),
+            VirtualPacketKind::OsData => Self::OsData(payload.to_vec()),
+            VirtualPacketKind::EndOfTransmissionAcknowledge => Self::EndOfTransmissionAcknowledge,
+            VirtualPacketKind::ParameterSet => {
+                let id = payload.read_u16::<BE>()?;
+                let length = payload.read_u16::<BE>()? as usize;
+                let kind = ParameterKind::from_repr(id).ok_or(UnknownParameterKindError(id))?;
+                let data = payload.get(..length).ok_or(InvalidPayload)?;
+                Self::ParameterSet(Parameter::from_payload(kind, data)?)
+            }
+            VirtualPacketKind::ModifyVariable => Self::ModifyVariable(payload.to_vec()),
             VirtualPacketKind::SetModeAcknowledge => Self::SetModeAcknowledge,
             VirtualPacketKind::DataAcknowledge => Self::DataAcknowledge,
             VirtualPacketKind::Wait => Self::Wait(payload.read_u32::<BE>()?),
             VirtualPacketKind::EndOfTransmission => Self::EndOfTransmission,
             VirtualPacketKind::Error => {
-                Self::Error(DeviceError::from_repr(payload.read_u16::<BE>()?).unwrap())
-            }
-            kind => {
-                todo!("received unhandled packet kind {:?}", kind)
+                let code = payload.read_u16::<BE>()?;
+                Self::Error(DeviceError::from_repr(code).ok_or(UnknownDeviceError(code))?)
             }
+            kind => return Err(NotImplemented(kind).into()),
         })
     }
 }
 
-#[derive(Error, Debug)]
-pub struct UnknownPacketKindError(pub u16);
-impl fmt::Display for UnknownPacketKindError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "unknown virtual packet kind {}", self.0)
+/// Checks the value of a raw-level acknowledgment. It's normally 0xe000, no one knows why.
+/// libticalcs only rejects values where neither byte matches, so the same is accepted here.
+fn check_acknowledgment(contents: u16) -> Result<(), InvalidPayload> {
+    let [high, low] = contents.to_be_bytes();
+    if high != 0xe0 && low != 0x00 {
+        return Err(InvalidPayload);
     }
+
+    Ok(())
 }
 
+/// Puts a virtual packet back together from the raw packets it was split into.
+#[derive(Debug, Default)]
+struct Reassembler {
+    bytes: Vec<u8>,
+}
+
+impl Reassembler {
+    /// Adds the next raw packet, returning whether it was the last one.
+    fn push(&mut self, packet: RawPacket) -> anyhow::Result<bool> {
+        match packet {
+            RawPacket::VirtualData(payload) => {
+                if self.bytes.is_empty() && payload.len() >= 4 {
+                    // The first chunk starts with the size of the whole packet, so large
+                    // packets like screenshots (~150KB over ~150 chunks) can be allocated
+                    // once up front. Capped since the size hasn't been checked yet.
+                    let size = (u32_from_bytes(&payload[0..4]) as usize).saturating_add(6);
+                    self.bytes.reserve(size.min(MAX_PREALLOCATION));
+                }
+                self.bytes.extend_from_slice(&payload);
+                Ok(false)
+            }
+            RawPacket::FinalVirtData(payload) => {
+                self.bytes.extend_from_slice(&payload);
+                Ok(true)
+            }
+            packet => Err(raw::WrongPacketKind {
+                expected: RawPacketKind::VirtData,
+                received: packet.kind(),
+            }
+            .into()),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("unknown device error code {0:#06x}")]
+pub struct UnknownDeviceError(pub u16);
+
+#[derive(Error, Debug)]
+#[error("decoding {0:?} packets isn't implemented yet")]
+pub struct NotImplemented(pub VirtualPacketKind);
+
+#[derive(Error, Debug)]
+#[error("can't send {0:?} packets")]
+pub struct Unsendable(pub VirtualPacketKind);
+
+#[derive(Error, Debug)]
+#[error("virtual packet wasn't fully received within {0:?}")]
+pub struct PacketTimedOut(pub Duration);
+
 #[derive(Error, Debug)]
 #[error("wrong packet kind: expected {expected:?}, received {received:?}")]
 pub struct WrongPacketKind {
@@ -356,3 +537,397 @@ impl WrongPacketKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+    use crate::{dusb::WrongAttributeLength, mock, model::Model};
+
+    /// Payloads laid out like the calculator sends them, used to check that truncating them
+    /// never panics.
+    const CORPUS: &[(VirtualPacketKind, &[u8])] = &[
+        (
+            VirtualPacketKind::SetMode,
+            &[0, 3, 0, 1, 0, 0, 0, 0, 0x7d, 0xd0],
+        ),
+        (
+            VirtualPacketKind::ParameterResponse,
+            &[0, 2, 0, 0x1e, 0, 0, 2, 0x01, 0x40, 0, 0x22, 1],
+        ),
+        (
+            VirtualPacketKind::VariableHeader,
+            &[
+                0, 4, b'S', b't', b'r', b'1', 0, 0, 2, 0, 0x01, 0, 0, 4, 0, 0, 0, 6, 0, 0x41, 0, 0,
+                1, 0,
+            ],
+        ),
+        (VirtualPacketKind::ParameterSet, &[0, 0x24, 0, 1, 1]),
+        (VirtualPacketKind::Wait, &[0, 0, 0, 0x64]),
+        (VirtualPacketKind::Error, &[0, 0x12]),
+    ];
+
+    fn encode(kind: VirtualPacketKind, payload: &[u8]) -> Vec<u8> {
+        encode_id(kind as u16, payload)
+    }
+
+    fn encode_id(id: u16, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&id.to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    /// The lengths of the raw packets a `VariableContents` packet is split into, with whether
+    /// each is final. The virtual packet's header adds 6 bytes to `contents`.
+    fn chunk_lengths(contents: usize, max_size: u32) -> Vec<(usize, bool)> {
+        VirtualPacket::VariableContents(vec![0; contents])
+            .into_raw_packets(max_size)
+            .unwrap()
+            .into_iter()
+            .map(|packet| match packet {
+                RawPacket::VirtualData(payload) => (payload.len(), false),
+                RawPacket::FinalVirtData(payload) => (payload.len(), true),
+                packet => panic!("unexpected {:?}", packet.kind()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn raw_packet_boundaries() {
+        let max_size = 16;
+        assert_eq!(chunk_lengths(9, max_size), [(15, true)]);
+        assert_eq!(chunk_lengths(10, max_size), [(16, true)]);
+        assert_eq!(chunk_lengths(11, max_size), [(16, false), (1, true)]);
+        assert_eq!(chunk_lengths(26, max_size), [(16, false), (16, true)]);
+    }
+
+    #[test]
+    fn reassembles_many_chunks() {
+        // About the size of a CE screenshot
+        let contents: Vec<u8> = (0..153_600).map(|i| (i % 251) as u8).collect();
+        let packets = VirtualPacket::VariableContents(contents.clone())
+            .into_raw_packets(1018)
+            .unwrap();
+        assert!(packets.len() > 150);
+
+        let mut reassembler = Reassembler::default();
+        let count = packets.len();
+        for (index, packet) in packets.into_iter().enumerate() {
+            assert_eq!(reassembler.push(packet).unwrap(), index == count - 1);
+        }
+
+        match VirtualPacket::try_from_bytes(&reassembler.bytes).unwrap() {
+            VirtualPacket::VariableContents(received) => assert_eq!(received, contents),
+            packet => panic!("unexpected {:?}", VirtualPacketKind::from(&packet)),
+        }
+    }
+
+    #[test]
+    fn preallocation_is_capped() {
+        let mut reassembler = Reassembler::default();
+        let mut payload = vec![0xff; 4];
+        payload.extend_from_slice(&[0x00, 0x0d]);
+        reassembler.push(RawPacket::VirtualData(payload)).unwrap();
+
+        assert!(reassembler.bytes.capacity() <= MAX_PREALLOCATION);
+    }
+
+    #[test]
+    fn keeps_unavailable_attributes() {
+        // Size, then Archived marked as invalid with code 0x01
+        let payload = [
+            0, 4, b'S', b't', b'r', b'1', 0, 0, 2, 0, 0x01, 0, 0, 4, 0, 0, 0, 6, 0, 0x03, 1,
+        ];
+        let VirtualPacket::VariableHeader(variable) =
+            VirtualPacket::from_payload(VirtualPacketKind::VariableHeader, &payload).unwrap()
+        else {
+            panic!("not a variable header");
+        };
+
+        assert_eq!(variable.size(), Some(6));
+        assert_eq!(variable.unavailable, [Unavailable { id: 0x03, code: 1 }]);
+    }
+
+    #[test]
+    fn header_attribute_with_wrong_length_is_error() {
+        // Version, sent with 2 bytes of data instead of 1
+        let payload = [0, 1, b'A', 0, 0, 1, 0, 0x08, 0, 0, 2, 0, 1];
+        let err =
+            VirtualPacket::from_payload(VirtualPacketKind::VariableHeader, &payload).unwrap_err();
+
+        let err = err.downcast_ref::<WrongAttributeLength>().unwrap();
+        assert_eq!(err.kind, VariableAttributeKind::Version);
+        assert_eq!((err.expected, err.received), (1, 2));
+    }
+
+    #[test]
+    fn request_to_send_encodes_archived() {
+        let variable = Variable {
+            name: VariableName::from("A"),
+            attributes: vec![
+                VariableAttribute::Archived(true),
+                VariableAttribute::Locked(false),
+            ],
+            unavailable: Vec::new(),
+        };
+        let payload = Vec::try_from(VirtualPacket::RequestToSend(variable)).unwrap();
+
+        assert_eq!(
+            payload,
+            [0, 1, b'A', 0, 2, 0, 0x03, 0, 1, 1, 0, 0x41, 0, 1, 0]
+        );
+    }
+
+    #[test]
+    fn theta_name_round_trip() {
+        // θA, with no attributes
+        let payload = [0, 2, 0x5b, b'A', 0, 0, 0];
+        let VirtualPacket::VariableHeader(variable) =
+            VirtualPacket::from_payload(VirtualPacketKind::VariableHeader, &payload).unwrap()
+        else {
+            panic!("not a variable header");
+        };
+        assert_eq!(variable.name.as_bytes(), [0x5b, b'A']);
+        assert_eq!(variable.name.to_string(), "θA");
+
+        let sent = Vec::try_from(VirtualPacket::RequestToSend(variable)).unwrap();
+        assert_eq!(sent, [0, 2, 0x5b, b'A', 0, 0]);
+    }
+
+    #[test]
+    fn unmodeled_kind_is_unknown() {
+        let packet = VirtualPacket::try_from_bytes(&encode_id(0x0014, &[1, 2])).unwrap();
+        assert!(
+            matches!(packet, VirtualPacket::Unknown(0x0014, ref payload) if payload == &[1, 2])
+        );
+        assert_eq!(packet.id(), 0x0014);
+        assert_eq!(Vec::try_from(packet).unwrap(), [1, 2]);
+    }
+
+    #[test]
+    fn receive_only_packet_is_unsendable() {
+        let result = Vec::try_from(VirtualPacket::Wait(100));
+        assert!(matches!(result, Err(Unsendable(VirtualPacketKind::Wait))));
+
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let err = VirtualPacket::DataAcknowledge
+            .send(&mut calculator)
+            .unwrap_err();
+        assert!(err.is::<Unsendable>());
+        assert!(mock.writes().is_empty());
+    }
+
+    #[test]
+    fn undecodable_kind_is_not_implemented() {
+        let payload = [0, 0, 0, 1, 0, 0x01, 0, 1, 0, 1, 0, 1, 1];
+        let err =
+            VirtualPacket::from_payload(VirtualPacketKind::DirectoryRequest, &payload).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(NotImplemented(VirtualPacketKind::DirectoryRequest))
+        ));
+    }
+
+    #[test]
+    fn acknowledgment_values() {
+        assert!(check_acknowledgment(0xe000).is_ok());
+        assert!(check_acknowledgment(0xe0ff).is_ok());
+        // Only one byte has to match
+        assert!(check_acknowledgment(0x0100).is_ok());
+        assert!(check_acknowledgment(0x0101).is_err());
+        assert!(check_acknowledgment(0xffff).is_err());
+    }
+
+    #[test]
+    fn parameter_request_reads_count_once() {
+        // Name, ScreenWidth and ScreenHeight, then 2 trailing bytes
+        let payload = [0, 3, 0, 0x02, 0, 0x1e, 0, 0x1f, 0xff, 0xff];
+        let VirtualPacket::ParameterRequest(parameters) =
+            VirtualPacket::from_payload(VirtualPacketKind::ParameterRequest, &payload).unwrap()
+        else {
+            panic!("not a parameter request");
+        };
+        assert!(matches!(
+            parameters[..],
+            [
+                ParameterKind::Name,
+                ParameterKind::ScreenWidth,
+                ParameterKind::ScreenHeight
+            ]
+        ));
+
+        let short = VirtualPacket::from_payload(VirtualPacketKind::ParameterRequest, &payload[..6]);
+        assert!(short.is_err());
+    }
+
+    #[test]
+    fn parameter_response_keeps_unavailable() {
+        // ScreenWidth of 320, then ScreenContents marked as invalid with code 0x01, as sent by
+        // models that can't send their screen
+        let payload = [0, 2, 0, 0x1e, 0, 0, 2, 0x01, 0x40, 0, 0x22, 1];
+        let VirtualPacket::ParameterResponse(parameters) =
+            VirtualPacket::from_payload(VirtualPacketKind::ParameterResponse, &payload).unwrap()
+        else {
+            panic!("not a parameter response");
+        };
+
+        assert_eq!(parameters.screen_width(), Some(320));
+        assert_eq!(parameters.unavailable, [Unavailable { id: 0x22, code: 1 }]);
+    }
+
+    #[test]
+    fn parameter_response_getters() {
+        // Name, ScreenWidth, ScreenHeight and FreeAppPages
+        let mut payload = vec![0, 4, 0, 0x02, 0, 0, 14];
+        payload.extend_from_slice(b"TI-84 Plus CE\0");
+        payload.extend_from_slice(&[0, 0x1e, 0, 0, 2, 0x01, 0x40]);
+        payload.extend_from_slice(&[0, 0x1f, 0, 0, 2, 0, 0xf0]);
+        payload.extend_from_slice(&[0, 0x13, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0x5b]);
+        let VirtualPacket::ParameterResponse(parameters) =
+            VirtualPacket::from_payload(VirtualPacketKind::ParameterResponse, &payload).unwrap()
+        else {
+            panic!("not a parameter response");
+        };
+
+        assert_eq!(parameters.name(), Some("TI-84 Plus CE"));
+        assert_eq!(parameters.screen_width(), Some(320));
+        assert_eq!(parameters.screen_height(), Some(240));
+        assert_eq!(parameters.free_app_pages(), Some(0x5b));
+        assert_eq!(parameters.total_app_pages(), None);
+        assert!(parameters.unavailable.is_empty());
+    }
+
+    #[test]
+    fn corpus_decodes() {
+        for (kind, payload) in CORPUS {
+            let packet = VirtualPacket::try_from_bytes(&encode(*kind, payload)).unwrap();
+            assert_eq!(VirtualPacketKind::from(&packet), *kind);
+        }
+    }
+
+    #[test]
+    fn truncated_payloads_dont_panic() {
+        // Not every truncation is an error, `SetMode` only reads the first 2 bytes
+        for (kind, payload) in CORPUS {
+            for length in 0..payload.len() {
+                let _ = VirtualPacket::from_payload(*kind, &payload[..length]);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_packets_are_errors() {
+        for (kind, payload) in CORPUS {
+            let bytes = encode(*kind, payload);
+            for length in 0..bytes.len() {
+                assert!(
+                    VirtualPacket::try_from_bytes(&bytes[..length]).is_err(),
+                    "{kind:?} truncated to {length} bytes"
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn random_packets_never_panic(
+            size in prop_oneof![any::<u32>(), 0..64u32],
+            kind in prop_oneof![
+                any::<u16>(),
+                0..0x20u16,
+                Just(0xaa00),
+                Just(0xbb00),
+                Just(0xdd00),
+                Just(0xee00),
+            ],
+            payload in vec(any::<u8>(), 0..256),
+        ) {
+            let mut bytes = size.to_be_bytes().to_vec();
+            bytes.extend_from_slice(&kind.to_be_bytes());
+            bytes.extend_from_slice(&payload);
+
+            let _ = VirtualPacket::try_from_bytes(&bytes);
+            if let Some(kind) = VirtualPacketKind::from_repr(kind) {
+                let _ = VirtualPacket::from_payload(kind, &payload);
+            }
+        }
+
+        /// However big the size at the start of a packet claims to be, no more than
+        /// [`MAX_PREALLOCATION`] is set aside for it before the data actually arrives.
+        #[test]
+        fn reassembly_allocation_is_bounded(
+            chunks in vec(vec(any::<u8>(), 0..64), 1..8),
+        ) {
+            let mut reassembler = Reassembler::default();
+            let mut received = 0;
+            for chunk in chunks {
+                received += chunk.len();
+                reassembler.push(RawPacket::VirtualData(chunk)).unwrap();
+            }
+
+            prop_assert!(reassembler.bytes.capacity() <= MAX_PREALLOCATION.max(2 * received));
+        }
+    }
+
+    /// A virtual packet small enough to arrive as a single raw packet.
+    fn single_packet(kind: VirtualPacketKind, payload: &[u8]) -> Vec<u8> {
+        mock::raw_packet(4, &mock::virtual_packet(kind, payload))
+    }
+
+    #[test]
+    fn acknowledgments() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        let acknowledge = |calculator: &mut Calculator| {
+            VirtualPacket::receive_acknowledge(calculator, VirtualPacketKind::DataAcknowledge)
+        };
+
+        mock.send_raw(&single_packet(VirtualPacketKind::Wait, &[0, 0, 0, 10]));
+        mock.send_raw(&single_packet(VirtualPacketKind::DataAcknowledge, &[]));
+        acknowledge(&mut calculator).unwrap();
+
+        mock.send_raw(&single_packet(VirtualPacketKind::Error, &[0, 0x0c]));
+        let err = acknowledge(&mut calculator).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(DeviceError::OutOfMemory)));
+
+        mock.send_raw(&single_packet(VirtualPacketKind::EndOfTransmission, &[]));
+        let err = acknowledge(&mut calculator).unwrap_err();
+        assert!(err.is::<WrongPacketKind>());
+    }
+
+    #[test]
+    fn buffer_size_request_before_acknowledgment() {
+        let (mut calculator, mock) = mock::connect(Model::Ti84PlusCe);
+        mock.send_raw(&mock::raw_packet(1, &[0, 0, 0x04, 0]));
+        mock.send_raw(&mock::raw_packet(5, &[0xe0, 0x00]));
+
+        VirtualPacket::wait_for_acknowledge(&mut calculator).unwrap();
+
+        let size = calculator.max_raw_packet_size.to_be_bytes();
+        assert_eq!(mock.writes(), [mock::raw_packet(2, &size)]);
+        assert_eq!(mock.pending_len(), 0);
+    }
+
+    #[test]
+    fn slow_packet_times_out() {
+        let mock = mock::Mock::new();
+        let mut calculator = mock.connect(
+            Calculator::builder(Model::Ti84PlusCe)
+                .timeout(mock::TIMEOUT)
+                .packet_timeout(Duration::from_millis(30)),
+        );
+        // Each read is quick enough on its own, but the whole packet takes about 100ms
+        mock.set_read_delay(Duration::from_millis(10));
+        mock.set_read_limit(8);
+        let packet = mock::virtual_packet(VirtualPacketKind::VariableContents, &[0; 40]);
+        let mut chunks = packet.chunks(10).peekable();
+        while let Some(chunk) = chunks.next() {
+            let kind = if chunks.peek().is_none() { 4 } else { 3 };
+            mock.send_raw(&mock::raw_packet(kind, chunk));
+        }
+
+        let err = VirtualPacket::receive(&mut calculator).unwrap_err();
+        assert!(err.is::<PacketTimedOut>());
+    }
+}
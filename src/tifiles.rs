@@ -0,0 +1,509 @@
+use std::io::{self, Read, Write};
+
+use byteorder::{ReadBytesExt, LE};
+use thiserror::Error;
+
+use crate::dusb::VariableName;
+
+/// The signature at the start of every 83+/84+ family variable file.
+pub const SIGNATURE: &[u8; 11] = b"**TI83F*\x1a\x0a\x00";
+pub const COMMENT_LENGTH: usize = 42;
+pub const NAME_LENGTH: usize = 8;
+
+/// Set in an entry's flags when the variable is stored in the archive.
+const ARCHIVED_FLAG: u8 = 0x80;
+
+#[derive(Debug, Clone)]
+pub struct TiFile {
+    pub comment: String,
+    pub entries: Vec<TiFileEntry>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TiFileEntry {
+    pub name: VariableName,
+    pub type_id: u8,
+    pub version: u8,
+    pub archived: bool,
+    /// The variable's data exactly as the calculator sends it, including any length prefix.
+    pub data: Vec<u8>,
+}
+
+/// The metadata at the start of a TI file, up to and including the first entry's header.
+#[derive(Debug, Clone)]
+pub struct TiFileHeader {
+    pub comment: String,
+    pub name: VariableName,
+    pub type_id: u8,
+    /// Length of the first entry's data.
+    pub data_length: u16,
+}
+
+#[derive(Debug, Error)]
+#[error("not a TI file (wrong signature)")]
+pub struct BadSignature;
+
+#[derive(Debug, Error)]
+#[error(
+    "TI file is corrupted: checksum is {expected:#06x}, but the data sums to {calculated:#06x}"
+)]
+pub struct ChecksumMismatch {
+    pub expected: u16,
+    pub calculated: u16,
+}
+
+#[derive(Debug, Error)]
+#[error("{length} bytes is more than a TI file can hold ({} bytes)", u16::MAX)]
+pub struct TooLarge {
+    pub length: usize,
+}
+
+#[derive(Debug, Error)]
+#[error("apps can't be saved as a variable file, they're Flash files")]
+pub struct AppNotVariable;
+
+/// The signature at the start of Flash files (apps and OSes).
+pub const FLASH_SIGNATURE: &[u8; 8] = b"**TIFL**";
+/// Everything before the data in a Flash file.
+const FLASH_HEADER_LENGTH: usize = 78;
+/// The [`FlashFile::data_type`] of apps.
+pub const FLASH_APP: u8 = 0x24;
+
+/// An app or OS in the Flash file format (`.8ck`/`.8ek`).
+#[derive(Debug, Clone)]
+pub struct FlashFile {
+    pub name: String,
+    /// 0x73 for the 83+/84+ family, 0x13 for the 83PCE/84+CE.
+    pub device_type: u8,
+    /// 0x24 for apps, 0x23 for OSes.
+    pub data_type: u8,
+    pub pages: Vec<FlashPage>,
+}
+
+/// One page of a Flash file. Files for the CE aren't paged and hold a single "page" with all
+/// of the data.
+#[derive(Debug, Clone)]
+pub struct FlashPage {
+    pub page: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Error)]
+#[error("{name} isn't an app (Flash data type {data_type:#04x})")]
+pub struct NotAnApp {
+    pub name: String,
+    pub data_type: u8,
+}
+
+#[derive(Debug, Error)]
+pub enum InvalidFlashFile {
+    #[error("not a Flash file (wrong signature)")]
+    BadSignature,
+    #[error("invalid Intel hex record on line {0}")]
+    BadRecord(usize),
+    #[error("checksum mismatch in Intel hex record on line {0}")]
+    ChecksumMismatch(usize),
+}
+
+impl TiFile {
+    pub fn new(entries: Vec<TiFileEntry>) -> Self {
+        Self {
+            comment: String::from("Created by tirs"),
+            entries,
+        }
+    }
+
+    /// Writes the file, failing with [`TooLarge`] if the entries together are over 64 KiB.
+    pub fn write<W: Write>(&self, mut writer: W) -> anyhow::Result<()> {
+        let mut data = Vec::new();
+        for entry in &self.entries {
+            entry.write(&mut data)?;
+        }
+        let data_length = u16_length(&data)?;
+
+        let mut comment = self.comment.as_bytes().to_vec();
+        comment.resize(COMMENT_LENGTH, 0);
+
+        writer.write_all(SIGNATURE)?;
+        writer.write_all(&comment)?;
+        writer.write_all(&data_length.to_le_bytes())?;
+        writer.write_all(&data)?;
+        writer.write_all(&checksum(&data).to_le_bytes())?;
+
+        Ok(())
+    }
+
+    /// Reads a TI file with any number of entries, like a group (`.8xg`) file. The checksum
+    /// after the entries is checked, so a truncated or corrupted file fails to read.
+    pub fn read(mut bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut signature = [0; SIGNATURE.len()];
+        bytes.read_exact(&mut signature)?;
+        if &signature != SIGNATURE {
+            return Err(BadSignature.into());
+        }
+
+        let mut comment = [0; COMMENT_LENGTH];
+        bytes.read_exact(&mut comment)?;
+        let data_length = bytes.read_u16::<LE>()? as usize;
+        let mut data = bytes
+            .get(..data_length)
+            .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        bytes = &bytes[data_length..];
+
+        let expected = bytes.read_u16::<LE>()?;
+        let calculated = checksum(data);
+        if expected != calculated {
+            return Err(ChecksumMismatch {
+                expected,
+                calculated,
+            }
+            .into());
+        }
+
+        let mut entries = Vec::new();
+        while !data.is_empty() {
+            entries.push(TiFileEntry::read(&mut data)?);
+        }
+
+        Ok(Self {
+            comment: trim_padding(&comment),
+            entries,
+        })
+    }
+}
+
+impl TiFileEntry {
+    /// Reads one entry and moves `bytes` past it.
+    pub fn read(bytes: &mut &[u8]) -> anyhow::Result<Self> {
+        // Older files leave out the version and flags, making the header 2 bytes shorter
+        let header_length = bytes.read_u16::<LE>()?;
+        let _data_length = bytes.read_u16::<LE>()?;
+        let type_id = bytes.read_u8()?;
+        let mut name = [0; NAME_LENGTH];
+        bytes.read_exact(&mut name)?;
+        let (version, flags) = if header_length >= 0x0d {
+            (bytes.read_u8()?, bytes.read_u8()?)
+        } else {
+            (0, 0)
+        };
+
+        let data_length = bytes.read_u16::<LE>()? as usize;
+        let mut data = vec![0; data_length];
+        bytes.read_exact(&mut data)?;
+
+        Ok(Self {
+            name: VariableName(
+                name.split(|byte| *byte == 0)
+                    .next()
+                    .unwrap_or_default()
+                    .to_vec(),
+            ),
+            type_id,
+            version,
+            archived: flags & ARCHIVED_FLAG != 0,
+            data,
+        })
+    }
+
+    /// Appends the entry, header and all, to `bytes`. Fails with [`TooLarge`] if the data is
+    /// over 64 KiB.
+    pub fn write(&self, bytes: &mut Vec<u8>) -> Result<(), TooLarge> {
+        let data_length = u16_length(&self.data)?;
+        let mut name = self.name.as_bytes().to_vec();
+        name.resize(NAME_LENGTH, 0);
+
+        bytes.extend_from_slice(&0x0du16.to_le_bytes()); // Length of the entry header
+        bytes.extend_from_slice(&data_length.to_le_bytes());
+        bytes.push(self.type_id);
+        bytes.extend_from_slice(&name);
+        bytes.push(self.version);
+        bytes.push(if self.archived { ARCHIVED_FLAG } else { 0 });
+        bytes.extend_from_slice(&data_length.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+
+        Ok(())
+    }
+}
+
+/// The length of `data` as stored in a TI file, which can't be more than a `u16`.
+fn u16_length(data: &[u8]) -> Result<u16, TooLarge> {
+    u16::try_from(data.len()).map_err(|_| TooLarge { length: data.len() })
+}
+
+/// The checksum stored at the end of the file, which is the lower 16 bits of the sum of the
+/// data section's bytes.
+pub fn checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16))
+}
+
+/// Reads the metadata of a TI file without going through the rest of it.
+pub fn read_header(mut bytes: &[u8]) -> anyhow::Result<TiFileHeader> {
+    let mut signature = [0; SIGNATURE.len()];
+    bytes.read_exact(&mut signature)?;
+    if &signature != SIGNATURE {
+        return Err(BadSignature.into());
+    }
+
+    let mut comment = [0; COMMENT_LENGTH];
+    bytes.read_exact(&mut comment)?;
+    let _file_data_length = bytes.read_u16::<LE>()?;
+
+    let _entry_header_length = bytes.read_u16::<LE>()?;
+    let data_length = bytes.read_u16::<LE>()?;
+    let type_id = bytes.read_u8()?;
+    let mut name = [0; NAME_LENGTH];
+    bytes.read_exact(&mut name)?;
+
+    Ok(TiFileHeader {
+        comment: trim_padding(&comment),
+        name: VariableName(
+            name.split(|byte| *byte == 0)
+                .next()
+                .unwrap_or_default()
+                .to_vec(),
+        ),
+        type_id,
+        data_length,
+    })
+}
+
+fn trim_padding(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .to_owned()
+}
+
+impl FlashFile {
+    pub fn read(mut bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() < FLASH_HEADER_LENGTH || &bytes[..FLASH_SIGNATURE.len()] != FLASH_SIGNATURE {
+            return Err(InvalidFlashFile::BadSignature.into());
+        }
+
+        let header = &bytes[..FLASH_HEADER_LENGTH];
+        let name_length = (header[16] as usize).min(NAME_LENGTH);
+        let name = trim_padding(&header[17..17 + name_length]);
+        let device_type = header[48];
+        let data_type = header[49];
+
+        bytes = &bytes[74..];
+        let data_length = bytes.read_u32::<LE>()? as usize;
+        let data = bytes
+            .get(..data_length)
+            .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+
+        // Files for the older models hold their pages as Intel hex, newer ones are just binary
+        let pages = if data.first() == Some(&b':') {
+            read_intel_hex(data)?
+        } else {
+            vec![FlashPage {
+                page: 0,
+                data: data.to_vec(),
+            }]
+        };
+
+        Ok(Self {
+            name,
+            device_type,
+            data_type,
+            pages,
+        })
+    }
+
+    /// All of the pages' data back to back, as sent to the calculator.
+    pub fn data(&self) -> Vec<u8> {
+        self.pages
+            .iter()
+            .flat_map(|page| page.data.iter().copied())
+            .collect()
+    }
+}
+
+/// Reads the pages out of Intel hex, where TI uses the extended segment address record (type
+/// 02) to start each page.
+fn read_intel_hex(data: &[u8]) -> Result<Vec<FlashPage>, InvalidFlashFile> {
+    let mut pages: Vec<FlashPage> = Vec::new();
+
+    let lines = String::from_utf8_lossy(data);
+    for (line_number, line) in lines
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+    {
+        if line.is_empty() {
+            continue;
+        }
+
+        let record = line
+            .strip_prefix(':')
+            .filter(|record| record.len() % 2 == 0 && record.len() >= 10)
+            .and_then(|record| {
+                (0..record.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&record[i..i + 2], 16).ok())
+                    .collect::<Option<Vec<u8>>>()
+            })
+            .ok_or(InvalidFlashFile::BadRecord(line_number))?;
+
+        let length = record[0] as usize;
+        if record.len() != length + 5 {
+            return Err(InvalidFlashFile::BadRecord(line_number));
+        }
+        if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(InvalidFlashFile::ChecksumMismatch(line_number));
+        }
+
+        let data = &record[4..4 + length];
+        match record[3] {
+            0x00 => match pages.last_mut() {
+                Some(page) => page.data.extend_from_slice(data),
+                None => return Err(InvalidFlashFile::BadRecord(line_number)),
+            },
+            0x01 => break,
+            0x02 if length == 2 => pages.push(FlashPage {
+                page: u16::from_be_bytes([data[0], data[1]]),
+                data: Vec::new(),
+            }),
+            _ => return Err(InvalidFlashFile::BadRecord(line_number)),
+        }
+    }
+
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group() -> TiFile {
+        TiFile::new(vec![
+            TiFileEntry {
+                name: VariableName::from("Str1"),
+                type_id: 0x04,
+                version: 0,
+                archived: false,
+                data: vec![4, 0, b'T', b'e', b's', b't'],
+            },
+            TiFileEntry {
+                name: VariableName::from("PROG"),
+                type_id: 0x05,
+                version: 1,
+                archived: true,
+                data: vec![2, 0, 0xde, 0x2a],
+            },
+        ])
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut bytes = Vec::new();
+        group().write(&mut bytes).unwrap();
+        let read = TiFile::read(&bytes).unwrap();
+
+        assert_eq!(read.comment, group().comment);
+        assert_eq!(read.entries.len(), 2);
+        for (read, written) in read.entries.iter().zip(group().entries) {
+            assert_eq!(read.name, written.name);
+            assert_eq!(read.type_id, written.type_id);
+            assert_eq!(read.version, written.version);
+            assert_eq!(read.archived, written.archived);
+            assert_eq!(read.data, written.data);
+        }
+    }
+
+    #[test]
+    fn corrupted_byte_fails_checksum() {
+        let mut bytes = Vec::new();
+        group().write(&mut bytes).unwrap();
+        // The last byte of the second entry's data
+        let last = bytes.len() - 3;
+        bytes[last] ^= 0x01;
+
+        let err = TiFile::read(&bytes).unwrap_err();
+        assert!(err.is::<ChecksumMismatch>());
+    }
+
+    #[test]
+    fn oversized_entry_is_rejected() {
+        let mut group = group();
+        group.entries[0].data = vec![0; 0x10000];
+
+        let err = group.write(Vec::new()).unwrap_err();
+        assert!(err.is::<TooLarge>());
+    }
+
+    /// A Flash file around `data`, with the header filled in the way TI's own files are.
+    fn flash_file(data: &[u8]) -> Vec<u8> {
+        let mut bytes = FLASH_SIGNATURE.to_vec();
+        bytes.resize(FLASH_HEADER_LENGTH, 0);
+        bytes[16] = 4;
+        bytes[17..21].copy_from_slice(b"Test");
+        bytes[48] = 0x73;
+        bytes[49] = FLASH_APP;
+        bytes[74..78].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    fn flash_file_pages() {
+        let hex = b":020000020000FC\r\n:04000000DEADBEEFC4\r\n\
+            :020000020001FB\r\n:020000001234B8\r\n:00000001FF\r\n";
+        let file = FlashFile::read(&flash_file(hex)).unwrap();
+
+        assert_eq!(file.name, "Test");
+        assert_eq!(file.device_type, 0x73);
+        assert_eq!(file.data_type, FLASH_APP);
+        let pages: Vec<_> = file.pages.iter().map(|page| page.page).collect();
+        assert_eq!(pages, [0, 1]);
+        assert_eq!(file.data(), [0xde, 0xad, 0xbe, 0xef, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn binary_flash_file_is_one_page() {
+        let file = FlashFile::read(&flash_file(&[0x81, 0x0f, 0, 0])).unwrap();
+
+        assert_eq!(file.pages.len(), 1);
+        assert_eq!(file.data().len(), 4);
+    }
+
+    #[test]
+    fn corrupted_hex_record_fails_checksum() {
+        let err = read_intel_hex(b":020000020000FC\n:04000000DEADBEEFC5\n").unwrap_err();
+        assert!(matches!(err, InvalidFlashFile::ChecksumMismatch(2)));
+    }
+
+    /// A program HELLO saved by TI Connect, containing `Disp "`.
+    fn program_file() -> Vec<u8> {
+        let mut bytes = SIGNATURE.to_vec();
+        let mut comment = b"Single file dated Sat Jun 01 12:00:00 2024".to_vec();
+        comment.resize(COMMENT_LENGTH, 0);
+        bytes.extend_from_slice(&comment);
+        bytes.extend_from_slice(&[0x15, 0]);
+        bytes.extend_from_slice(&[0x0d, 0, 4, 0, 0x05]);
+        bytes.extend_from_slice(b"HELLO\0\0\0");
+        bytes.extend_from_slice(&[0, 0, 4, 0, 2, 0, 0xde, 0x2a]);
+        bytes.extend_from_slice(&[0x98, 0x02]);
+        bytes
+    }
+
+    #[test]
+    fn header() {
+        let header = read_header(&program_file()).unwrap();
+
+        assert_eq!(header.comment, "Single file dated Sat Jun 01 12:00:00 2024");
+        assert_eq!(header.name, VariableName::from("HELLO"));
+        assert_eq!(header.type_id, 0x05);
+        assert_eq!(header.data_length, 4);
+        assert!(TiFile::read(&program_file()).is_ok());
+    }
+
+    #[test]
+    fn bad_signature() {
+        let mut bytes = program_file();
+        bytes[2] = b'X';
+
+        assert!(read_header(&bytes).unwrap_err().is::<BadSignature>());
+        assert!(TiFile::read(&bytes).unwrap_err().is::<BadSignature>());
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use rusb::{Device, DeviceHandle, Direction, GlobalContext, TransferType, UsbContext};
+use thiserror::Error;
+
+use crate::{calculator_model, model::Model};
+
+/// The USB operations a [`Calculator`](crate::Calculator) uses to talk to the calculator, so it
+/// can be run against something other than a real device.
+pub trait Transport: Send {
+    fn read_bulk(&mut self, endpoint: u8, buf: &mut [u8], timeout: Duration)
+        -> rusb::Result<usize>;
+
+    fn write_bulk(&mut self, endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize>;
+
+    /// Whether the device is still plugged in, without sending it anything.
+    fn is_connected(&self) -> bool;
+
+    /// Opens the same device again after it was unplugged or reset and claims `interface`,
+    /// failing with [`CalculatorReset`] if it can't be found.
+    fn reconnect(&mut self, model: Model, interface: u8) -> anyhow::Result<()>;
+}
+
+/// A calculator plugged in over USB.
+pub struct UsbTransport {
+    handle: DeviceHandle<GlobalContext>,
+    /// Which calculator this is, to find it again in [`Transport::reconnect`].
+    identity: DeviceIdentity,
+}
+
+impl UsbTransport {
+    pub fn new(handle: DeviceHandle<GlobalContext>) -> Self {
+        Self {
+            identity: DeviceIdentity::of(&handle),
+            handle,
+        }
+    }
+}
+
+impl Transport for UsbTransport {
+    fn read_bulk(
+        &mut self,
+        endpoint: u8,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> rusb::Result<usize> {
+        self.handle.read_bulk(endpoint, buf, timeout)
+    }
+
+    fn write_bulk(&mut self, endpoint: u8, buf: &[u8], timeout: Duration) -> rusb::Result<usize> {
+        self.handle.write_bulk(endpoint, buf, timeout)
+    }
+
+    fn is_connected(&self) -> bool {
+        let device = self.handle.device();
+        rusb::devices().is_ok_and(|devices| {
+            devices.iter().any(|other| {
+                other.bus_number() == device.bus_number() && other.address() == device.address()
+            })
+        })
+    }
+
+    /// Only the same calculator is used (see [`DeviceIdentity`]), even if others are plugged in.
+    fn reconnect(&mut self, model: Model, interface: u8) -> anyhow::Result<()> {
+        let device = rusb::devices()?
+            .iter()
+            .filter(|device| calculator_model(device) == Some(model))
+            .find(|device| self.identity.matches(device))
+            .ok_or(CalculatorReset)?;
+
+        self.handle = device.open()?;
+        self.handle.claim_interface(interface)?;
+        Ok(())
+    }
+}
+
+/// A USB transfer failure, sorted into the cases a user can actually do something about.
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("calculator was disconnected")]
+    Disconnected,
+    #[error("timed out waiting for the calculator")]
+    TimedOut,
+    #[error("access to the calculator was denied (on Linux, a udev rule is needed to access it without root)")]
+    AccessDenied,
+    #[error("USB endpoint stalled")]
+    Stall,
+    #[error("transfer was cancelled")]
+    Cancelled,
+    #[error(transparent)]
+    Other(rusb::Error),
+}
+
+/// Totals for the USB transfers made while stats are being collected. The elapsed time only
+/// counts time spent in USB calls, not the time between them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferStats {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub elapsed: Duration,
+}
+
+impl TransferStats {
+    /// The average speed, or 0 if nothing has taken any time yet.
+    pub fn kilobytes_per_second(&self) -> f64 {
+        if self.elapsed.is_zero() {
+            return 0.0;
+        }
+
+        (self.bytes_sent + self.bytes_received) as f64 / 1024.0 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Cancels the calculator's current read from another thread, or its next one if it isn't
+/// reading. See [`Calculator::cancel_token`](crate::Calculator::cancel_token).
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the read should be cancelled, clearing the cancellation so later reads go ahead.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// A USB interface that can be used to talk to the calculator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbInterface {
+    pub number: u8,
+    pub read_endpoint: u8,
+    pub write_endpoint: u8,
+    /// The largest USB packet the read endpoint sends.
+    pub max_packet_size: u16,
+}
+
+#[derive(Debug, Error)]
+pub enum InterfaceNotFound {
+    #[error("calculator has no USB interface with bulk endpoints")]
+    NoBulkInterface,
+    #[error("USB interface {0} doesn't exist or has no bulk endpoints")]
+    NotBulk(u8),
+}
+
+/// An endpoint of one of the device's interfaces, as far as choosing one matters.
+#[derive(Debug, Clone, Copy)]
+struct Endpoint {
+    address: u8,
+    transfer_type: TransferType,
+    direction: Direction,
+    max_packet_size: u16,
+}
+
+/// Finds the interface with a bulk endpoint in each direction, or checks that the given one
+/// has them.
+pub fn find_interface<T: UsbContext>(
+    device: &Device<T>,
+    number: Option<u8>,
+) -> anyhow::Result<UsbInterface> {
+    let config = device.active_config_descriptor()?;
+    let interfaces = config
+        .interfaces()
+        .flat_map(|interface| interface.descriptors())
+        .map(|descriptor| {
+            let endpoints = descriptor
+                .endpoint_descriptors()
+                .map(|endpoint| Endpoint {
+                    address: endpoint.address(),
+                    transfer_type: endpoint.transfer_type(),
+                    direction: endpoint.direction(),
+                    max_packet_size: endpoint.max_packet_size(),
+                })
+                .collect();
+            (descriptor.interface_number(), endpoints)
+        });
+
+    Ok(select_interface(interfaces, number)?)
+}
+
+/// Picks the first of `interfaces`, given as their number and endpoints, with a bulk endpoint
+/// in each direction. If `number` is given, only that interface is considered.
+fn select_interface(
+    interfaces: impl IntoIterator<Item = (u8, Vec<Endpoint>)>,
+    number: Option<u8>,
+) -> Result<UsbInterface, InterfaceNotFound> {
+    let found = interfaces
+        .into_iter()
+        .filter(|(interface, _)| number.is_none() || number == Some(*interface))
+        .find_map(|(interface, endpoints)| {
+            let bulk_endpoint = |direction| {
+                endpoints.iter().find(|endpoint| {
+                    endpoint.transfer_type == TransferType::Bulk && endpoint.direction == direction
+                })
+            };
+            let read_endpoint = bulk_endpoint(Direction::In)?;
+
+            Some(UsbInterface {
+                number: interface,
+                read_endpoint: read_endpoint.address,
+                write_endpoint: bulk_endpoint(Direction::Out)?.address,
+                max_packet_size: read_endpoint.max_packet_size,
+            })
+        });
+
+    found.ok_or(match number {
+        Some(number) => InterfaceNotFound::NotBulk(number),
+        None => InterfaceNotFound::NoBulkInterface,
+    })
+}
+
+/// Which calculator a connection is to, so the same one can be found again after it
+/// reconnects. The USB address changes when a device reconnects, so it's identified by its
+/// serial number if it has one, or else by the port it's plugged into.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub bus: u8,
+    pub ports: Vec<u8>,
+    pub serial: Option<String>,
+}
+
+impl DeviceIdentity {
+    pub fn of<T: UsbContext>(handle: &DeviceHandle<T>) -> Self {
+        let device = handle.device();
+        Self {
+            bus: device.bus_number(),
+            ports: device.port_numbers().unwrap_or_default(),
+            serial: read_serial(handle),
+        }
+    }
+
+    /// Whether `device` is the calculator this identifies. A device that can't be opened to
+    /// read its serial number doesn't match.
+    pub fn matches<T: UsbContext>(&self, device: &Device<T>) -> bool {
+        match &self.serial {
+            Some(serial) => device
+                .open()
+                .ok()
+                .and_then(|handle| read_serial(&handle))
+                .is_some_and(|other| other == *serial),
+            None => {
+                device.bus_number() == self.bus
+                    && device.port_numbers().is_ok_and(|ports| ports == self.ports)
+            }
+        }
+    }
+}
+
+fn read_serial<T: UsbContext>(handle: &DeviceHandle<T>) -> Option<String> {
+    let descriptor = handle.device().device_descriptor().ok()?;
+    handle
+        .read_serial_number_string_ascii(&descriptor)
+        .ok()
+        .filter(|serial| !serial.is_empty())
+}
+
+/// The calculator refused to write a new variable, so it's the whole calculator that's
+/// restricted (e.g. by exam mode) rather than the variable being locked or archived.
+#[derive(Debug, Error)]
+#[error("calculator isn't accepting variables, it may be in exam mode")]
+pub struct CalculatorLocked;
+
+/// A read buffer too small to hold a whole raw packet, set with
+/// [`CalculatorBuilder::read_buffer_size`](crate::CalculatorBuilder::read_buffer_size).
+#[derive(Debug, Error)]
+#[error("read buffer of {read_buffer_size} bytes can't hold a {buffer_size} byte raw packet")]
+pub struct ReadBufferTooSmall {
+    pub read_buffer_size: usize,
+    pub buffer_size: u32,
+}
+
+/// The calculator went away in the middle of an operation and couldn't be reconnected to. This
+/// usually means it reset, which low batteries can cause.
+#[derive(Debug, Error)]
+#[error("calculator reset or was unplugged during the operation")]
+pub struct CalculatorReset;
+
+impl TransferError {
+    /// Whether `err` comes from the calculator disconnecting, either directly or through a read.
+    pub fn is_disconnect(err: &anyhow::Error) -> bool {
+        err.chain().any(|cause| {
+            let cause = match cause.downcast_ref::<io::Error>() {
+                Some(io_err) => match io_err.get_ref() {
+                    Some(inner) => inner,
+                    None => return false,
+                },
+                None => cause,
+            };
+            matches!(cause.downcast_ref(), Some(TransferError::Disconnected))
+        })
+    }
+}
+
+impl From<rusb::Error> for TransferError {
+    fn from(value: rusb::Error) -> Self {
+        match value {
+            rusb::Error::NoDevice => Self::Disconnected,
+            rusb::Error::Timeout => Self::TimedOut,
+            rusb::Error::Access => Self::AccessDenied,
+            rusb::Error::Pipe => Self::Stall,
+            err => Self::Other(err),
+        }
+    }
+}
+
+impl From<TransferError> for io::Error {
+    fn from(value: TransferError) -> Self {
+        let kind = match value {
+            TransferError::Disconnected => io::ErrorKind::NotConnected,
+            TransferError::TimedOut => io::ErrorKind::TimedOut,
+            TransferError::AccessDenied => io::ErrorKind::PermissionDenied,
+            // Not `Interrupted`, since `read_exact` retries those
+            TransferError::Stall | TransferError::Cancelled | TransferError::Other(_) => {
+                io::ErrorKind::Other
+            }
+        };
+
+        io::Error::new(kind, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn usb_errors_are_classified() {
+        assert!(matches!(
+            TransferError::from(rusb::Error::NoDevice),
+            TransferError::Disconnected
+        ));
+        assert!(matches!(
+            TransferError::from(rusb::Error::Timeout),
+            TransferError::TimedOut
+        ));
+        assert!(matches!(
+            TransferError::from(rusb::Error::Access),
+            TransferError::AccessDenied
+        ));
+        assert!(matches!(
+            TransferError::from(rusb::Error::Pipe),
+            TransferError::Stall
+        ));
+        assert!(matches!(
+            TransferError::from(rusb::Error::Io),
+            TransferError::Other(rusb::Error::Io)
+        ));
+    }
+
+    #[test]
+    fn io_error_round_trip() {
+        for (error, kind) in [
+            (rusb::Error::NoDevice, io::ErrorKind::NotConnected),
+            (rusb::Error::Timeout, io::ErrorKind::TimedOut),
+            (rusb::Error::Access, io::ErrorKind::PermissionDenied),
+            (rusb::Error::Pipe, io::ErrorKind::Other),
+        ] {
+            let io_err = io::Error::from(TransferError::from(error));
+            assert_eq!(io_err.kind(), kind);
+            let inner = io_err.get_ref().unwrap().downcast_ref::<TransferError>();
+            assert_eq!(
+                inner.unwrap().to_string(),
+                TransferError::from(error).to_string()
+            );
+        }
+        // Cancelling isn't `Interrupted`, which would be retried
+        let io_err = io::Error::from(TransferError::Cancelled);
+        assert_eq!(io_err.kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn disconnects_are_found_through_reads_and_context() {
+        let direct = anyhow::Error::from(TransferError::Disconnected);
+        assert!(TransferError::is_disconnect(&direct));
+
+        let read: anyhow::Result<()> = Err(io::Error::from(TransferError::Disconnected).into());
+        let read = read.context("reading a packet").unwrap_err();
+        assert!(TransferError::is_disconnect(&read));
+
+        let timeout = anyhow::Error::from(io::Error::from(TransferError::TimedOut));
+        assert!(!TransferError::is_disconnect(&timeout));
+        let plain = anyhow::Error::from(io::Error::from(io::ErrorKind::NotConnected));
+        assert!(!TransferError::is_disconnect(&plain));
+    }
+
+    fn endpoint(address: u8, transfer_type: TransferType) -> Endpoint {
+        Endpoint {
+            address,
+            transfer_type,
+            direction: if address & 0x80 != 0 {
+                Direction::In
+            } else {
+                Direction::Out
+            },
+            max_packet_size: 64,
+        }
+    }
+
+    #[test]
+    fn bulk_interface_is_selected() {
+        // A HID-like interface first, then the one DUSB uses
+        let interfaces = || {
+            vec![
+                (0, vec![endpoint(0x83, TransferType::Interrupt)]),
+                (
+                    1,
+                    vec![
+                        endpoint(0x81, TransferType::Bulk),
+                        endpoint(0x02, TransferType::Bulk),
+                    ],
+                ),
+            ]
+        };
+
+        let expected = UsbInterface {
+            number: 1,
+            read_endpoint: 0x81,
+            write_endpoint: 0x02,
+            max_packet_size: 64,
+        };
+        assert_eq!(select_interface(interfaces(), None).unwrap(), expected);
+        assert_eq!(select_interface(interfaces(), Some(1)).unwrap(), expected);
+        assert!(matches!(
+            select_interface(interfaces(), Some(0)),
+            Err(InterfaceNotFound::NotBulk(0))
+        ));
+        assert!(matches!(
+            select_interface(Vec::new(), None),
+            Err(InterfaceNotFound::NoBulkInterface)
+        ));
+    }
+}